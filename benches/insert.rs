@@ -0,0 +1,71 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use log4rs::append::Append;
+use x_log4rs_sqlite::{IdStrategy, JournalMode, SqliteLogAppender, Synchronous};
+
+const BUFFER_SIZES: [usize; 4] = [1, 64, 1024, 16384];
+
+// With random ids most of the time goes to updating the primary key index.
+const ID_STRATEGIES: [(&str, IdStrategy); 2] = [
+    ("uuid_v4", IdStrategy::UuidV4),
+    ("rowid", IdStrategy::Rowid),
+];
+
+fn append(appender: &SqliteLogAppender, i: usize) {
+    appender
+        .append(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .target("bench")
+                .module_path_static(Some("bench::insert"))
+                .file_static(Some("benches/insert.rs"))
+                .line(Some(42))
+                .args(format_args!("entry number {}", i))
+                .build(),
+        )
+        .unwrap();
+}
+
+// Writes out a full buffer of entries, with multi-row inserts and with one
+// insert per entry.
+fn insert(c: &mut Criterion) {
+    for (id_name, id_strategy) in ID_STRATEGIES {
+        insert_with_ids(c, id_name, id_strategy);
+    }
+}
+
+fn insert_with_ids(c: &mut Criterion, id_name: &str, id_strategy: IdStrategy) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group(format!("insert/{id_name}"));
+    for buffer_size in BUFFER_SIZES {
+        group.throughput(Throughput::Elements(buffer_size as u64));
+        for (name, multi_row_insert) in [("multi_row", true), ("single_row", false)] {
+            let path = dir.path().join(format!("{name}-{buffer_size}.sqlite"));
+            // One more, so that the appended entries are written by the
+            // measured flush only.
+            let appender = SqliteLogAppender::builder()
+                .path(&path)
+                .buffer_size(buffer_size + 1)
+                .journal_mode(JournalMode::Wal)
+                .synchronous(Synchronous::Normal)
+                .multi_row_insert(multi_row_insert)
+                .id_strategy(id_strategy)
+                .build()
+                .unwrap();
+            group.bench_function(BenchmarkId::new(name, buffer_size), |b| {
+                b.iter_batched(
+                    || {
+                        for i in 0..buffer_size {
+                            append(&appender, i);
+                        }
+                    },
+                    |()| appender.try_flush().unwrap(),
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert);
+criterion_main!(benches);
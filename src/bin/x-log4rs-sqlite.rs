@@ -0,0 +1,391 @@
+use anyhow::anyhow;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use x_log4rs_sqlite::{Field, LogEntry, LogFilter, LogReader};
+
+const DEFAULT_FORMAT: &str = "{ts} {level} {target} - {message}";
+
+/// Query and tail log databases written by the x-log4rs-sqlite appender.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the entries matching the filters.
+    Query {
+        #[command(flatten)]
+        common: CommonArgs,
+        /// Print at most that many entries.
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Skip that many first entries.
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        /// Print the newest entries first.
+        #[arg(long)]
+        newest_first: bool,
+    },
+    /// Print the last entries matching the filters.
+    Tail {
+        #[command(flatten)]
+        common: CommonArgs,
+        /// Number of the last entries to print.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        /// Keep printing the new entries as they're written.
+        #[arg(short, long)]
+        follow: bool,
+        /// How often to check for new entries with `--follow`.
+        #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+    },
+}
+
+#[derive(Args)]
+struct CommonArgs {
+    /// Path to the SQLite database file.
+    path: PathBuf,
+    /// Name of the entry table.
+    #[arg(long, default_value = "entry")]
+    table: String,
+    /// Column a field is stored in, when the table was written with custom
+    /// `columns`, e.g. `ts=logged_at`; can be repeated.
+    #[arg(long = "column", value_name = "FIELD=COLUMN", value_parser = parse_column)]
+    columns: Vec<(Field, String)>,
+    /// Only the entries at that level or more severe.
+    #[arg(short, long)]
+    level: Option<log::Level>,
+    /// Only the entries logged at or after that time: UTC date and time, e.g.
+    /// `2023-09-26 18:00:00`, RFC 3339 time, or duration before now, e.g. `1h`.
+    #[arg(long, value_parser = parse_time)]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only the entries logged before that time, in the same format as `--since`.
+    #[arg(long, value_parser = parse_time)]
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only the entries whose target starts with that prefix.
+    #[arg(short, long)]
+    target: Option<String>,
+    /// Only the entries whose message contains that text.
+    #[arg(short, long)]
+    grep: Option<String>,
+    /// Line format, with `{field}` replaced by the entry fields: id, ts, level,
+    /// target, message, module_path, file, line, thread_name, thread_id,
+    /// process_id, hostname, formatted, and `{mdc.<key>}` by MDC entries.
+    #[arg(long, default_value = DEFAULT_FORMAT)]
+    format: String,
+    /// When to colour the levels.
+    #[arg(long, value_enum, default_value_t = Color::Auto)]
+    color: Color,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+fn parse_time(s: &str) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    if let Ok(ts) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Ok(ts.with_timezone(&chrono::Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(ts) = chrono::NaiveDateTime::parse_from_str(s, format) {
+            return Ok(ts.and_utc());
+        }
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    let duration = humantime::parse_duration(s)
+        .map_err(|_| anyhow!("expected date and time or duration, e.g. 1h"))?;
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| chrono::Utc::now().checked_sub_signed(duration))
+        .ok_or_else(|| anyhow!("duration is too long"))
+}
+
+fn parse_column(s: &str) -> anyhow::Result<(Field, String)> {
+    let (field, column) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected FIELD=COLUMN, e.g. ts=logged_at"))?;
+    Ok((field.parse()?, column.to_string()))
+}
+
+struct Printer {
+    format: String,
+    color: bool,
+}
+
+impl Printer {
+    fn new(common: &CommonArgs) -> Printer {
+        let color = match common.color {
+            Color::Auto => std::io::stdout().is_terminal(),
+            Color::Always => true,
+            Color::Never => false,
+        };
+        Printer {
+            format: common.format.clone(),
+            color,
+        }
+    }
+    fn level(&self, level: log::Level) -> String {
+        if !self.color {
+            return level.to_string();
+        }
+        let code = match level {
+            log::Level::Error => "31",
+            log::Level::Warn => "33",
+            log::Level::Info => "32",
+            log::Level::Debug => "34",
+            log::Level::Trace => "2",
+        };
+        format!("\x1b[{code}m{level}\x1b[0m")
+    }
+    fn field(&self, entry: &LogEntry, name: &str) -> Option<String> {
+        let opt = |v: &Option<String>| v.clone().unwrap_or_default();
+        let num = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+        let value = match name {
+            "id" => entry.id.clone(),
+            "ts" => entry.ts.format("%Y-%m-%d %H:%M:%S%.6f").to_string(),
+            "level" => self.level(entry.level),
+            "target" => opt(&entry.target),
+            "message" => entry.message.clone(),
+            "module_path" => opt(&entry.module_path),
+            "file" => opt(&entry.file),
+            "line" => num(entry.line.map(u64::from)),
+            "thread_name" => opt(&entry.thread_name),
+            "thread_id" => num(entry.thread_id),
+            "process_id" => num(entry.process_id.map(u64::from)),
+            "hostname" => opt(&entry.hostname),
+            "formatted" => opt(&entry.formatted),
+            _ => match name.strip_prefix("mdc.") {
+                Some(key) => entry.mdc.get(key).cloned().unwrap_or_default(),
+                None => return None,
+            },
+        };
+        Some(value)
+    }
+    // Unknown `{...}` placeholders are printed as they are.
+    fn line(&self, entry: &LogEntry) -> String {
+        let mut line = String::new();
+        let mut rest = self.format.as_str();
+        while let Some(start) = rest.find('{') {
+            line.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = rest
+                .find('}')
+                .and_then(|end| Some((end, self.field(entry, &rest[1..end])?)));
+            match value {
+                Some((end, value)) => {
+                    line.push_str(&value);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    line.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        line.push_str(rest);
+        line
+    }
+    fn print(&self, out: &mut impl Write, entry: &LogEntry) -> anyhow::Result<()> {
+        writeln!(out, "{}", self.line(entry).trim_end_matches('\n'))?;
+        Ok(())
+    }
+}
+
+fn reader(common: &CommonArgs) -> anyhow::Result<LogReader> {
+    let mut builder = LogReader::builder().table(&common.table);
+    for (field, column) in common.columns.iter() {
+        builder = builder.column(*field, column);
+    }
+    builder.open(&common.path)
+}
+
+fn filter(common: &CommonArgs) -> LogFilter {
+    let mut filter = LogFilter::new();
+    if let Some(level) = common.level {
+        filter = filter.min_level(level);
+    }
+    if let Some(since) = common.since {
+        filter = filter.since(since);
+    }
+    if let Some(until) = common.until {
+        filter = filter.until(until);
+    }
+    if let Some(target) = &common.target {
+        filter = filter.target_prefix(target);
+    }
+    if let Some(text) = &common.grep {
+        filter = filter.message_contains(text);
+    }
+    filter
+}
+
+fn query(
+    common: &CommonArgs,
+    limit: Option<usize>,
+    offset: usize,
+    newest_first: bool,
+) -> anyhow::Result<()> {
+    let reader = reader(common)?;
+    let printer = Printer::new(common);
+    let mut filter = filter(common).offset(offset).newest_first(newest_first);
+    if let Some(limit) = limit {
+        filter = filter.limit(limit);
+    }
+    let mut out = std::io::stdout().lock();
+    for entry in reader.entries(&filter) {
+        printer.print(&mut out, &entry?)?;
+    }
+    Ok(())
+}
+
+fn tail(common: &CommonArgs, lines: usize, follow: bool, interval: Duration) -> anyhow::Result<()> {
+    let reader = reader(common)?;
+    let printer = Printer::new(common);
+    let filter = filter(common);
+    // Taken before reading the last entries, so that the ones written in the
+    // meantime are printed either now or by the first poll.
+    let mut last_rowid = reader.max_rowid()?;
+    let mut entries = reader
+        .entries(&filter.clone().newest_first(true).limit(lines))
+        .collect::<anyhow::Result<Vec<LogEntry>>>()?;
+    entries.reverse();
+    loop {
+        let mut out = std::io::stdout().lock();
+        for entry in entries {
+            last_rowid = last_rowid.max(entry.rowid);
+            printer.print(&mut out, &entry)?;
+        }
+        out.flush()?;
+        drop(out);
+        if !follow {
+            return Ok(());
+        }
+        std::thread::sleep(interval);
+        entries = reader
+            .entries(&filter.clone().after_rowid(last_rowid))
+            .collect::<anyhow::Result<Vec<LogEntry>>>()?;
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let result = match &cli.command {
+        Command::Query {
+            common,
+            limit,
+            offset,
+            newest_first,
+        } => query(common, *limit, *offset, *newest_first),
+        Command::Tail {
+            common,
+            lines,
+            follow,
+            interval,
+        } => tail(common, *lines, *follow, *interval),
+    };
+    // Stop quietly when the output is closed, e.g. piped to `head`.
+    match result {
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> LogEntry {
+        LogEntry {
+            rowid: 1,
+            id: "01".to_string(),
+            ts: chrono::NaiveDate::from_ymd_opt(2023, 9, 26)
+                .unwrap()
+                .and_hms_micro_opt(18, 47, 26, 413370)
+                .unwrap(),
+            level: log::Level::Warn,
+            message: "disk full".to_string(),
+            target: Some("app::db".to_string()),
+            module_path: None,
+            file: None,
+            line: Some(42),
+            thread_name: None,
+            thread_id: None,
+            process_id: None,
+            hostname: None,
+            mdc: [("request_id".to_string(), "r1".to_string())].into(),
+            formatted: None,
+        }
+    }
+
+    fn printer(format: &str, color: bool) -> Printer {
+        Printer {
+            format: format.to_string(),
+            color,
+        }
+    }
+
+    #[test]
+    fn formats_line() {
+        assert_eq!(
+            printer(DEFAULT_FORMAT, false).line(&entry()),
+            "2023-09-26 18:47:26.413370 WARN app::db - disk full"
+        );
+        assert_eq!(
+            printer("{line} {mdc.request_id} {mdc.user} {file}|", false).line(&entry()),
+            "42 r1  |"
+        );
+        assert_eq!(
+            printer("{level}", true).line(&entry()),
+            "\x1b[33mWARN\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders() {
+        assert_eq!(
+            printer("{{message}} {unknown} {message", false).line(&entry()),
+            "{disk full} {unknown} {message"
+        );
+    }
+
+    #[test]
+    fn parses_time() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2023, 9, 26)
+            .unwrap()
+            .and_hms_milli_opt(18, 0, 0, 500)
+            .unwrap()
+            .and_utc();
+        assert_eq!(parse_time("2023-09-26 18:00:00.5").unwrap(), expected);
+        assert_eq!(parse_time("2023-09-26T18:00:00.5").unwrap(), expected);
+        assert_eq!(parse_time("2023-09-26T20:00:00.5+02:00").unwrap(), expected);
+        assert_eq!(
+            parse_time("2023-09-26").unwrap(),
+            expected
+                .date_naive()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+        );
+        let before = chrono::Utc::now() - chrono::Duration::hours(1);
+        let parsed = parse_time("1h").unwrap();
+        assert!(parsed >= before && parsed <= chrono::Utc::now() - chrono::Duration::minutes(59));
+        assert!(parse_time("yesterday").is_err());
+        assert!(parse_time("1000000years").is_err());
+    }
+}
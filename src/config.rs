@@ -0,0 +1,329 @@
+use anyhow::anyhow;
+use log4rs::append::rolling_file::policy::compound::roll::Roll;
+use log4rs::append::rolling_file::policy::compound::trigger::time::TimeTriggerConfig;
+use log4rs::encode::EncoderConfig;
+use serde::de::DeserializeOwned;
+use serde_value::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::Field;
+use crate::OverflowPolicy;
+use crate::RollTrigger;
+use crate::SqliteLogAppender;
+use crate::SqliteLogAppenderBuilder;
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqliteLogAppenderConfig {
+    path: String,
+    background: Option<Value>,
+    buffer_size: Option<Value>,
+    flush_interval: Option<Value>,
+    table: Option<Value>,
+    journal_mode: Option<Value>,
+    synchronous: Option<Value>,
+    busy_timeout: Option<Value>,
+    capture_thread_name: Option<Value>,
+    capture_thread_id: Option<Value>,
+    capture_process_id: Option<Value>,
+    capture_hostname: Option<Value>,
+    mdc_keys: Option<Value>,
+    mdc_all: Option<Value>,
+    encoder: Option<Value>,
+    columns: Option<Value>,
+    create_schema: Option<Value>,
+    storage: Option<Value>,
+    id_strategy: Option<Value>,
+    max_age: Option<Value>,
+    max_rows: Option<Value>,
+    max_db_size: Option<Value>,
+    retention_check_interval: Option<Value>,
+    retention_batch_size: Option<Value>,
+    incremental_vacuum: Option<Value>,
+    policy: Option<Value>,
+    full_text_search: Option<Value>,
+    max_buffer_size: Option<Value>,
+    overflow_policy: Option<Value>,
+    fallback: Option<Value>,
+    max_retries: Option<Value>,
+    retry_backoff: Option<Value>,
+    multi_row_insert: Option<Value>,
+}
+
+// `block`, `drop_newest`, `drop_oldest` or `drop_below: <level>`.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OverflowPolicyConfig {
+    Block,
+    DropNewest,
+    DropOldest,
+    DropBelow(String),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyConfig {
+    kind: Option<String>,
+    trigger: TriggerConfig,
+    roller: KindConfig,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum TriggerConfig {
+    Size { limit: Value },
+    Time(TimeTriggerConfig),
+}
+
+// Config of a log4rs component, created with the deserializer of its kind.
+#[derive(serde::Deserialize)]
+struct KindConfig {
+    kind: String,
+    #[serde(flatten)]
+    config: Value,
+}
+
+fn parse_field<T: DeserializeOwned>(name: &str, value: Option<Value>) -> anyhow::Result<Option<T>> {
+    value
+        .map(|v| T::deserialize(v).map_err(|e| anyhow!("Invalid `{}`: {}", name, e)))
+        .transpose()
+}
+
+fn parse_duration_field(name: &str, value: Option<Value>) -> anyhow::Result<Option<Duration>> {
+    parse_field::<String>(name, value)?
+        .map(|s| humantime::parse_duration(&s).map_err(|e| anyhow!("Invalid `{}`: {}", name, e)))
+        .transpose()
+}
+
+fn parse_str_field<T>(name: &str, value: Option<Value>) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    parse_field::<String>(name, value)?
+        .map(|s| s.parse().map_err(|e| anyhow!("Invalid `{}`: {}", name, e)))
+        .transpose()
+}
+
+// Accepts a number of bytes, or a string with a unit, e.g. `100MB` or `1 GiB`.
+fn parse_size_field(name: &str, value: Option<Value>) -> anyhow::Result<Option<u64>> {
+    match value {
+        Some(Value::String(s)) => parse_size(&s)
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid `{}`: {}", name, e)),
+        value => parse_field(name, value),
+    }
+}
+
+fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid size {:?}", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => {
+            return Err(anyhow!(
+                "unknown unit in size {:?}, expected one of: B, KB, MB, GB, KiB, MiB, GiB",
+                s
+            ))
+        }
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("size {:?} is too large", s))
+}
+
+fn parse_overflow_policy_field(value: Option<Value>) -> anyhow::Result<Option<OverflowPolicy>> {
+    let policy = match parse_field("overflow_policy", value)? {
+        Some(OverflowPolicyConfig::Block) => OverflowPolicy::Block,
+        Some(OverflowPolicyConfig::DropNewest) => OverflowPolicy::DropNewest,
+        Some(OverflowPolicyConfig::DropOldest) => OverflowPolicy::DropOldest,
+        Some(OverflowPolicyConfig::DropBelow(level)) => OverflowPolicy::DropBelow(
+            level
+                .parse()
+                .map_err(|_| anyhow!("Invalid `overflow_policy`: unknown level {:?}", level))?,
+        ),
+        None => return Ok(None),
+    };
+    Ok(Some(policy))
+}
+
+fn parse_policy_field(
+    value: Option<Value>,
+    deserializers: &log4rs::config::Deserializers,
+) -> anyhow::Result<Option<(RollTrigger, Box<dyn Roll>)>> {
+    let policy = match parse_field::<PolicyConfig>("policy", value)? {
+        Some(policy) => policy,
+        None => return Ok(None),
+    };
+    if let Some(kind) = policy.kind.filter(|k| k != "compound") {
+        return Err(anyhow!(
+            "Invalid `policy`: unknown kind {:?}, expected compound",
+            kind
+        ));
+    }
+    let trigger = match policy.trigger {
+        TriggerConfig::Size { limit } => {
+            RollTrigger::Size(parse_size_field("policy", Some(limit))?.unwrap_or_default())
+        }
+        TriggerConfig::Time(config) => {
+            if config.max_random_delay != 0 {
+                return Err(anyhow!(
+                    "Invalid `policy`: `max_random_delay` is not supported"
+                ));
+            }
+            RollTrigger::Time {
+                interval: config.interval,
+                modulate: config.modulate,
+            }
+        }
+    };
+    let roller = deserializers
+        .deserialize::<dyn Roll>(&policy.roller.kind, policy.roller.config)
+        .map_err(|e| anyhow!("Invalid `policy`: {}", e))?;
+    Ok(Some((trigger, roller)))
+}
+
+impl SqliteLogAppenderConfig {
+    fn into_builder(
+        self,
+        deserializers: &log4rs::config::Deserializers,
+    ) -> anyhow::Result<SqliteLogAppenderBuilder> {
+        let mut builder = SqliteLogAppender::builder().path(self.path);
+        if let Some(buffer_size) = parse_field("buffer_size", self.buffer_size)? {
+            builder = builder.buffer_size(buffer_size);
+        }
+        if let Some(flush_interval) = parse_duration_field("flush_interval", self.flush_interval)? {
+            builder = builder.flush_interval(flush_interval);
+        }
+        if let Some(table) = parse_field::<String>("table", self.table)? {
+            builder = builder.table(&table);
+        }
+        if let Some(journal_mode) = parse_str_field("journal_mode", self.journal_mode)? {
+            builder = builder.journal_mode(journal_mode);
+        }
+        if let Some(synchronous) = parse_str_field("synchronous", self.synchronous)? {
+            builder = builder.synchronous(synchronous);
+        }
+        if let Some(busy_timeout) = parse_duration_field("busy_timeout", self.busy_timeout)? {
+            builder = builder.busy_timeout(busy_timeout);
+        }
+        if let Some(background) = parse_field("background", self.background)? {
+            builder = builder.background(background);
+        }
+        if let Some(capture) = parse_field("capture_thread_name", self.capture_thread_name)? {
+            builder = builder.capture_thread_name(capture);
+        }
+        if let Some(capture) = parse_field("capture_thread_id", self.capture_thread_id)? {
+            builder = builder.capture_thread_id(capture);
+        }
+        if let Some(capture) = parse_field("capture_process_id", self.capture_process_id)? {
+            builder = builder.capture_process_id(capture);
+        }
+        if let Some(capture) = parse_field("capture_hostname", self.capture_hostname)? {
+            builder = builder.capture_hostname(capture);
+        }
+        if let Some(keys) = parse_field::<Vec<String>>("mdc_keys", self.mdc_keys)? {
+            builder = builder.mdc_keys(keys);
+        }
+        if let Some(mdc_all) = parse_field("mdc_all", self.mdc_all)? {
+            builder = builder.mdc_all(mdc_all);
+        }
+        if let Some(encoder) = parse_field::<EncoderConfig>("encoder", self.encoder)? {
+            let encoder = deserializers
+                .deserialize(&encoder.kind, encoder.config)
+                .map_err(|e| anyhow!("Invalid `encoder`: {}", e))?;
+            builder = builder.encoder(encoder);
+        }
+        if let Some(columns) = parse_field::<BTreeMap<String, String>>("columns", self.columns)? {
+            for (field, column) in columns {
+                let field = field
+                    .parse::<Field>()
+                    .map_err(|e| anyhow!("Invalid `columns`: {}", e))?;
+                builder = builder.column(field, &column);
+            }
+        }
+        if let Some(create_schema) = parse_field("create_schema", self.create_schema)? {
+            builder = builder.create_schema(create_schema);
+        }
+        if let Some(storage) = parse_str_field("storage", self.storage)? {
+            builder = builder.storage(storage);
+        }
+        if let Some(id_strategy) = parse_str_field("id_strategy", self.id_strategy)? {
+            builder = builder.id_strategy(id_strategy);
+        }
+        if let Some(max_age) = parse_duration_field("max_age", self.max_age)? {
+            builder = builder.max_age(max_age);
+        }
+        if let Some(max_rows) = parse_field("max_rows", self.max_rows)? {
+            builder = builder.max_rows(max_rows);
+        }
+        if let Some(max_db_size) = parse_size_field("max_db_size", self.max_db_size)? {
+            builder = builder.max_db_size(max_db_size);
+        }
+        if let Some(interval) =
+            parse_duration_field("retention_check_interval", self.retention_check_interval)?
+        {
+            builder = builder.retention_check_interval(interval);
+        }
+        if let Some(batch_size) = parse_field("retention_batch_size", self.retention_batch_size)? {
+            builder = builder.retention_batch_size(batch_size);
+        }
+        if let Some(incremental_vacuum) =
+            parse_field("incremental_vacuum", self.incremental_vacuum)?
+        {
+            builder = builder.incremental_vacuum(incremental_vacuum);
+        }
+        if let Some((trigger, roller)) = parse_policy_field(self.policy, deserializers)? {
+            builder = builder.rolling(trigger, roller);
+        }
+        if let Some(full_text_search) = parse_field("full_text_search", self.full_text_search)? {
+            builder = builder.full_text_search(full_text_search);
+        }
+        if let Some(max_buffer_size) = parse_field("max_buffer_size", self.max_buffer_size)? {
+            builder = builder.max_buffer_size(max_buffer_size);
+        }
+        if let Some(policy) = parse_overflow_policy_field(self.overflow_policy)? {
+            builder = builder.overflow_policy(policy);
+        }
+        if let Some(max_retries) = parse_field("max_retries", self.max_retries)? {
+            builder = builder.max_retries(max_retries);
+        }
+        if let Some(retry_backoff) = parse_duration_field("retry_backoff", self.retry_backoff)? {
+            builder = builder.retry_backoff(retry_backoff);
+        }
+        if let Some(multi_row_insert) = parse_field("multi_row_insert", self.multi_row_insert)? {
+            builder = builder.multi_row_insert(multi_row_insert);
+        }
+        if let Some(fallback) = parse_field::<KindConfig>("fallback", self.fallback)? {
+            let fallback = deserializers
+                .deserialize(&fallback.kind, fallback.config)
+                .map_err(|e| anyhow!("Invalid `fallback`: {}", e))?;
+            builder = builder.fallback(fallback);
+        }
+        Ok(builder)
+    }
+}
+
+pub struct SqliteLogAppenderDeserializer {}
+
+impl log4rs::config::Deserialize for SqliteLogAppenderDeserializer {
+    type Trait = dyn log4rs::append::Append;
+    type Config = SqliteLogAppenderConfig;
+
+    fn deserialize(
+        &self,
+        config: SqliteLogAppenderConfig,
+        deserializers: &log4rs::config::Deserializers,
+    ) -> anyhow::Result<Box<dyn log4rs::append::Append>> {
+        Ok(Box::new(config.into_builder(deserializers)?.build()?))
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::anyhow;
+
+/// Field of a log entry saved by the appender, see
+/// [`SqliteLogAppenderBuilder::column`](crate::SqliteLogAppenderBuilder::column).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Field {
+    Id,
+    Ts,
+    Level,
+    Message,
+    Target,
+    ModulePath,
+    File,
+    Line,
+    ThreadName,
+    ThreadId,
+    ProcessId,
+    Hostname,
+    Mdc,
+    Formatted,
+}
+
+impl Field {
+    pub(crate) const ALL: [Field; 14] = [
+        Field::Id,
+        Field::Ts,
+        Field::Level,
+        Field::Message,
+        Field::Target,
+        Field::ModulePath,
+        Field::File,
+        Field::Line,
+        Field::ThreadName,
+        Field::ThreadId,
+        Field::ProcessId,
+        Field::Hostname,
+        Field::Mdc,
+        Field::Formatted,
+    ];
+
+    /// Name of the field in the config file, also used as the default column name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Id => "id",
+            Field::Ts => "ts",
+            Field::Level => "level",
+            Field::Message => "message",
+            Field::Target => "target",
+            Field::ModulePath => "module_path",
+            Field::File => "file",
+            Field::Line => "line",
+            Field::ThreadName => "thread_name",
+            Field::ThreadId => "thread_id",
+            Field::ProcessId => "process_id",
+            Field::Hostname => "hostname",
+            Field::Mdc => "mdc",
+            Field::Formatted => "formatted",
+        }
+    }
+
+    pub(crate) fn is_required(&self) -> bool {
+        matches!(self, Field::Id | Field::Ts | Field::Level | Field::Message)
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Field> {
+        Field::ALL
+            .iter()
+            .find(|f| f.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                let names = Field::ALL.map(|f| f.name());
+                anyhow!(
+                    "unknown field {:?}, expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
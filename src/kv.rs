@@ -0,0 +1,118 @@
+use anyhow::anyhow;
+use rusqlite::types::Value;
+use std::collections::VecDeque;
+
+use crate::LogRecord;
+
+pub(crate) struct KeyValue {
+    key: String,
+    value: Value,
+    value_type: &'static str,
+}
+
+struct Collector<'a>(&'a mut Vec<KeyValue>);
+
+// Key-values of a buffered entry, as a source for the records forwarded to
+// the fallback appender.
+pub(crate) struct Pairs<'a>(pub(crate) &'a [KeyValue]);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for Collector<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        self.0.push(KeyValue::new(key.as_str(), &value));
+        Ok(())
+    }
+}
+
+impl log::kv::Source for Pairs<'_> {
+    fn visit<'kvs>(
+        &'kvs self,
+        visitor: &mut dyn log::kv::VisitSource<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        for kv in self.0 {
+            let value = match (&kv.value, kv.value_type) {
+                (Value::Integer(v), "bool") => log::kv::Value::from(*v != 0),
+                (Value::Integer(v), _) => log::kv::Value::from(*v),
+                (Value::Real(v), _) => log::kv::Value::from(*v),
+                (Value::Text(v), _) => log::kv::Value::from(v.as_str()),
+                _ => log::kv::Value::null(),
+            };
+            visitor.visit_pair(log::kv::Key::from_str(&kv.key), value)?;
+        }
+        Ok(())
+    }
+}
+
+impl KeyValue {
+    fn new(key: &str, value: &log::kv::Value) -> KeyValue {
+        let (value, value_type) = if let Some(v) = value.to_bool() {
+            (Value::Integer(v as i64), "bool")
+        } else if let Some(v) = value.to_i64() {
+            (Value::Integer(v), "i64")
+        } else if let Some(v) = value.to_u64() {
+            (Value::Text(v.to_string()), "u64")
+        } else if let Some(v) = value.to_f64() {
+            (Value::Real(v), "f64")
+        } else {
+            (Value::Text(value.to_string()), "str")
+        };
+        KeyValue {
+            key: key.to_string(),
+            value,
+            value_type,
+        }
+    }
+}
+
+pub(crate) fn key_values(record: &log::Record) -> anyhow::Result<Vec<KeyValue>> {
+    let mut kv = Vec::new();
+    record
+        .key_values()
+        .visit(&mut Collector(&mut kv))
+        .map_err(|e| anyhow!("Error reading key-values: {}", e))?;
+    Ok(kv)
+}
+
+pub(crate) fn create_table_if_not_exists(
+    conn: &rusqlite::Connection,
+    table: &str,
+) -> anyhow::Result<()> {
+    let table_sql = format!(
+        "create table if not exists {table}_kv (
+            entry_id varchar(128) not null,
+            key varchar(256) not null,
+            value,
+            value_type varchar(16) not null
+        )"
+    );
+    let index_entry_id_sql =
+        format!("create index if not exists {table}_kv_entry_id_i on {table}_kv (entry_id)");
+    let index_key_sql = format!("create index if not exists {table}_kv_key_i on {table}_kv (key)");
+    conn.execute(&table_sql, [])?;
+    conn.execute(&index_entry_id_sql, [])?;
+    conn.execute(&index_key_sql, [])?;
+    Ok(())
+}
+
+pub(crate) fn insert(
+    tx: &rusqlite::Transaction,
+    table: &str,
+    buf: &VecDeque<LogRecord>,
+    ids: &[Value],
+) -> rusqlite::Result<()> {
+    if buf.iter().all(|lr| lr.kv.is_empty()) {
+        return Ok(());
+    }
+    let mut stmt = tx.prepare_cached(&format!(
+        "insert into {table}_kv (entry_id, key, value, value_type) values (?1, ?2, ?3, ?4)"
+    ))?;
+    for (lr, id) in buf.iter().zip(ids) {
+        for kv in lr.kv.iter() {
+            stmt.execute(rusqlite::params![id, kv.key, kv.value, kv.value_type])?;
+        }
+    }
+    Ok(())
+}
@@ -1,118 +1,1743 @@
 use anyhow::anyhow;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
-struct SqliteLogAppender {
-    buf: Arc<RwLock<Vec<LogRecord>>>,
-    buf_size: usize,
-    file_name: String,
+mod config;
+mod field;
+#[cfg(feature = "kv")]
+mod kv;
+mod reader;
+mod retention;
+mod rolling;
+mod schema;
+mod stats;
+
+pub use config::SqliteLogAppenderConfig;
+pub use config::SqliteLogAppenderDeserializer;
+pub use field::Field;
+pub use reader::LogEntries;
+pub use reader::LogEntry;
+pub use reader::LogFilter;
+pub use reader::LogReader;
+pub use reader::LogReaderBuilder;
+pub use reader::SearchHit;
+pub use rolling::RollTrigger;
+pub use stats::Stats;
+pub use stats::StatsHandle;
+
+// How long the `block` policy keeps waiting for the buffer to be written out
+// after the appender is dropped in `background` mode.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// log4rs appender that writes log entries to a SQLite database.
+pub struct SqliteLogAppender {
+    writer: Writer,
+    options: Options,
+    ids: Arc<IdGenerator>,
+    stats: Arc<stats::Counters>,
+}
+
+/// Builder for [`SqliteLogAppender`], created with [`SqliteLogAppender::builder`].
+#[derive(Clone, Debug, Default)]
+pub struct SqliteLogAppenderBuilder {
+    options: Options,
+}
+
+#[derive(Clone, Debug)]
+struct Options {
+    path: PathBuf,
+    buffer_size: usize,
+    flush_interval: Option<Duration>,
+    table: String,
+    journal_mode: Option<JournalMode>,
+    synchronous: Option<Synchronous>,
+    busy_timeout: Option<Duration>,
+    background: bool,
+    capture_thread_name: bool,
+    capture_thread_id: bool,
+    capture_process_id: bool,
+    capture_hostname: bool,
+    mdc_keys: Vec<String>,
+    mdc_all: bool,
+    encoder: Option<Arc<dyn log4rs::encode::Encode>>,
+    columns: Vec<(Field, String)>,
+    create_schema: bool,
+    storage: Storage,
+    id_strategy: IdStrategy,
+    retention: retention::Retention,
+    rolling: Option<rolling::Rolling>,
+    full_text_search: bool,
+    max_buffer_size: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    fallback: Option<Arc<dyn log4rs::append::Append>>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    on_error: ErrorHandler,
+    multi_row_insert: bool,
+}
+
+/// SQLite journal mode, see `PRAGMA journal_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+/// SQLite synchronous mode, see `PRAGMA synchronous`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+/// How timestamps and levels are stored in the DB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Storage {
+    /// Timestamps as `2023-09-23 19:20:30.401272` text, levels as `INFO` etc.
+    Text,
+    /// Timestamps as integer microseconds since the Unix epoch, levels as
+    /// integers from 1 (`ERROR`) to 5 (`TRACE`).
+    Compact,
+}
+
+/// How the ids of the entries are generated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdStrategy {
+    /// Random UUID v4 as text.
+    UuidV4,
+    /// Time-ordered UUID v7 as text.
+    UuidV7,
+    /// Time-ordered ULID as text.
+    Ulid,
+    /// Integer assigned by SQLite, the `id` column is `integer primary key autoincrement`.
+    Rowid,
+}
+
+/// What to do with new entries when the buffer is full, because the entries
+/// can't be written to the DB. In `background` mode, the policy applies to
+/// the writer thread queue as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until the buffer is written out; blocks the threads that log.
+    Block,
+    /// Drop the new entry.
+    DropNewest,
+    /// Drop the oldest buffered entry.
+    DropOldest,
+    /// Drop the entries less severe than the level, the new one or the oldest
+    /// buffered one; when there are none, drop the oldest buffered entry.
+    DropBelow(log::Level),
 }
 
 struct LogRecord {
-    id: String,
-    level: String,
-    ts: String,
+    id: Option<String>,
+    log_level: log::Level,
+    level: rusqlite::types::Value,
+    ts: rusqlite::types::Value,
     message: String,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    thread_name: Option<String>,
+    thread_id: Option<usize>,
+    mdc: Vec<Option<String>>,
+    mdc_json: Option<String>,
+    formatted: Option<String>,
+    #[cfg(feature = "kv")]
+    kv: Vec<kv::KeyValue>,
+}
+
+enum Writer {
+    Caller {
+        sink: Arc<Mutex<Sink>>,
+        _timer: Option<FlushTimer>,
+    },
+    Thread(WriterThread),
+}
+
+struct IdGenerator {
+    strategy: IdStrategy,
+    ulid: Mutex<ulid::Generator>,
+}
+
+struct Sink {
+    buf: VecDeque<LogRecord>,
+    buf_since: Option<Instant>,
+    retention_checked: Option<Instant>,
+    next_roll_time: Option<chrono::DateTime<chrono::Local>>,
+    conn: Option<rusqlite::Connection>,
+    insert_fields: Vec<Field>,
+    // Rows written by one multi-row insert statement, and its SQL.
+    insert_rows: usize,
+    insert_rows_sql: String,
+    insert_sql: String,
+    #[cfg(feature = "kv")]
+    write_kv: bool,
+    process_id: Option<u32>,
+    hostname: Option<String>,
+    ids: Arc<IdGenerator>,
+    stats: Arc<stats::Counters>,
+    // Failed attempts to write the current batch, and when the next attempt
+    // can be made; until then the entries stay in the buffer.
+    failures: u32,
+    next_attempt: Option<Instant>,
+    // Set once the batch in the buffer is counted as failed, so that the later
+    // attempts to write it don't count it again.
+    batch_failed: bool,
+    // Set when the appender is dropped, after which the `block` policy waits
+    // only until `shutdown_deadline`.
+    stopping: Arc<AtomicBool>,
+    shutdown_deadline: Option<Instant>,
+    options: Options,
+}
+
+// Entries and flush requests passed to the writer thread. It's bounded, and
+// the overflow policy applies to the entries the writer thread hasn't taken
+// yet as well.
+struct Queue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    // Notified when entries or flush requests are added, or the queue is closed.
+    added: Condvar,
+    // Notified when the writer thread takes the entries, or the queue is closed.
+    taken: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    entries: VecDeque<LogRecord>,
+    flushes: Vec<mpsc::Sender<anyhow::Result<()>>>,
+    // Set when the appender is dropped or the writer thread exits.
+    closed: bool,
+}
+
+struct WriterThread {
+    queue: Arc<Queue>,
+    handle: Option<thread::JoinHandle<()>>,
+    overflow_policy: OverflowPolicy,
+    stats: Arc<stats::Counters>,
+    stopping: Arc<AtomicBool>,
+    on_error: ErrorHandler,
+}
+
+struct FlushTimer {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+    on_error: ErrorHandler,
+}
+
+// Errors that can't be returned to log4rs, from the writer and timer threads
+// or the buffered writes, go to the `on_error` callback, or to stderr like
+// the log4rs ones.
+type OnError = dyn Fn(&anyhow::Error) + Send + Sync;
+
+#[derive(Clone, Default)]
+struct ErrorHandler(Option<Arc<OnError>>);
+
+impl ErrorHandler {
+    fn handle(&self, e: &anyhow::Error) {
+        match &self.0 {
+            Some(on_error) => on_error(e),
+            None => eprintln!("log4rs: {:#}", e),
+        }
+    }
+}
+
+impl std::fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+thread_local! {
+    static IS_WRITER_THREAD: Cell<bool> = const { Cell::new(false) };
+    static IN_PANIC_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+// A panic while the sink is locked leaves it usable, the buffer is only
+// modified by infallible operations.
+fn lock_sink(sink: &Mutex<Sink>) -> std::sync::MutexGuard<'_, Sink> {
+    sink.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+// In the panic hook the sink may be locked by the panicking thread itself,
+// e.g. when an `on_error` callback or the fallback appender panics, so there
+// the lock is waited for only for a while.
+fn try_lock_sink(sink: &Mutex<Sink>) -> anyhow::Result<std::sync::MutexGuard<'_, Sink>> {
+    if !IN_PANIC_HOOK.get() {
+        return Ok(lock_sink(sink));
+    }
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        match sink.try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(std::sync::TryLockError::Poisoned(e)) => return Ok(e.into_inner()),
+            Err(std::sync::TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(std::sync::TryLockError::WouldBlock) => {
+                return Err(anyhow!(
+                    "Appender is locked, possibly by the panicking thread"
+                ))
+            }
+        }
+    }
+}
+
+/// Installs a panic hook that logs the panic message at `ERROR` level and
+/// flushes the logger before running the previously installed hook, so that
+/// buffered entries and the panic itself are persisted before the process
+/// unwinds or aborts.
+///
+/// Panics of the writer thread are not logged, as it can't write to its own
+/// queue. When the appender is locked by the panicking thread, the entry is
+/// not written and the error is reported instead.
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !IS_WRITER_THREAD.get() {
+            IN_PANIC_HOOK.set(true);
+            let thread = thread::current();
+            log::error!(
+                target: "panic",
+                "thread '{}' {}",
+                thread.name().unwrap_or("<unnamed>"),
+                info
+            );
+            log::logger().flush();
+            IN_PANIC_HOOK.set(false);
+        }
+        previous(info);
+    }));
+}
+
+fn is_connection_error(e: &rusqlite::Error) -> bool {
+    match e {
+        rusqlite::Error::SqliteFailure(e, _) => matches!(
+            e.code,
+            rusqlite::ErrorCode::SystemIoFailure
+                | rusqlite::ErrorCode::CannotOpen
+                | rusqlite::ErrorCode::DatabaseCorrupt
+                | rusqlite::ErrorCode::NotADatabase
+        ),
+        _ => false,
+    }
+}
+
+// Errors worth retrying the batch after: another connection holds a lock for
+// longer than the busy timeout, or the file is temporarily unavailable.
+fn is_transient_error(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<rusqlite::Error>() {
+        Some(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+            e.code,
+            rusqlite::ErrorCode::DatabaseBusy
+                | rusqlite::ErrorCode::DatabaseLocked
+                | rusqlite::ErrorCode::SystemIoFailure
+        ),
+        _ => false,
+    }
+}
+
+fn is_valid_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            path: PathBuf::new(),
+            buffer_size: 1024,
+            flush_interval: None,
+            table: "entry".to_string(),
+            journal_mode: None,
+            synchronous: None,
+            busy_timeout: Some(Duration::from_secs(5)),
+            background: false,
+            capture_thread_name: false,
+            capture_thread_id: false,
+            capture_process_id: false,
+            capture_hostname: false,
+            mdc_keys: Vec::new(),
+            mdc_all: false,
+            encoder: None,
+            columns: Vec::new(),
+            create_schema: true,
+            storage: Storage::Text,
+            id_strategy: IdStrategy::UuidV4,
+            retention: retention::Retention::default(),
+            rolling: None,
+            full_text_search: false,
+            max_buffer_size: None,
+            overflow_policy: OverflowPolicy::DropOldest,
+            fallback: None,
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(50),
+            on_error: ErrorHandler::default(),
+            multi_row_insert: true,
+        }
+    }
+}
+
+impl Options {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Err(anyhow!("Invalid `path`: must not be empty"));
+        }
+        if self.buffer_size == 0 {
+            return Err(anyhow!("Invalid `buffer_size`: must be greater than 0"));
+        }
+        if self.max_buffer_size() < self.buffer_size {
+            return Err(anyhow!(
+                "Invalid `max_buffer_size`: must not be less than `buffer_size`"
+            ));
+        }
+        if self.flush_interval == Some(Duration::ZERO) {
+            return Err(anyhow!("Invalid `flush_interval`: must be greater than 0"));
+        }
+        if !is_valid_identifier(&self.table) {
+            return Err(anyhow!(
+                "Invalid `table`: {:?} is not a valid SQL identifier",
+                self.table
+            ));
+        }
+        for (i, key) in self.mdc_keys.iter().enumerate() {
+            if !is_valid_identifier(key) {
+                return Err(anyhow!(
+                    "Invalid `mdc_keys`: {:?} can't be used in a column name",
+                    key
+                ));
+            }
+            if self.mdc_keys[..i].contains(key) {
+                return Err(anyhow!("Invalid `mdc_keys`: {:?} is listed twice", key));
+            }
+        }
+        self.retention.validate()?;
+        if let Some(rolling) = &self.rolling {
+            rolling.validate()?;
+            if !self.create_schema {
+                return Err(anyhow!(
+                    "Invalid `policy`: rolling requires `create_schema` to be enabled"
+                ));
+            }
+        }
+        if self.full_text_search && !self.create_schema {
+            return Err(anyhow!(
+                "Invalid `full_text_search`: the index requires `create_schema` to be enabled"
+            ));
+        }
+        for (_, column) in self.columns.iter() {
+            if !is_valid_identifier(column) {
+                return Err(anyhow!(
+                    "Invalid `columns`: {:?} is not a valid SQL identifier",
+                    column
+                ));
+            }
+        }
+        let columns = Field::ALL
+            .iter()
+            .map(|f| self.column(*f).to_string())
+            .chain(self.mdc_columns())
+            .collect::<Vec<String>>();
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|c| c.eq_ignore_ascii_case(column)) {
+                return Err(anyhow!(
+                    "Invalid `columns`: column {:?} is used for more than one field",
+                    column
+                ));
+            }
+        }
+        Ok(())
+    }
+    fn max_buffer_size(&self) -> usize {
+        self.max_buffer_size
+            .unwrap_or(self.buffer_size.saturating_mul(16))
+    }
+    fn column(&self, field: Field) -> &str {
+        self.columns
+            .iter()
+            .rev()
+            .find(|(f, _)| *f == field)
+            .map(|(_, column)| column.as_str())
+            .unwrap_or(field.name())
+    }
+    fn mdc_columns(&self) -> Vec<String> {
+        self.mdc_keys.iter().map(|k| format!("mdc_{k}")).collect()
+    }
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+impl std::str::FromStr for JournalMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<JournalMode> {
+        match s.to_ascii_lowercase().as_str() {
+            "delete" => Ok(JournalMode::Delete),
+            "truncate" => Ok(JournalMode::Truncate),
+            "persist" => Ok(JournalMode::Persist),
+            "memory" => Ok(JournalMode::Memory),
+            "wal" => Ok(JournalMode::Wal),
+            "off" => Ok(JournalMode::Off),
+            _ => Err(anyhow!(
+                "unknown journal mode {:?}, expected one of: delete, truncate, persist, memory, wal, off",
+                s
+            )),
+        }
+    }
+}
+
+impl Synchronous {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+}
+
+impl std::str::FromStr for Synchronous {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Synchronous> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Synchronous::Off),
+            "normal" => Ok(Synchronous::Normal),
+            "full" => Ok(Synchronous::Full),
+            "extra" => Ok(Synchronous::Extra),
+            _ => Err(anyhow!(
+                "unknown synchronous mode {:?}, expected one of: off, normal, full, extra",
+                s
+            )),
+        }
+    }
+}
+
+impl Storage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Storage::Text => "text",
+            Storage::Compact => "compact",
+        }
+    }
+    fn ts_value(&self, ts: chrono::DateTime<chrono::Utc>) -> rusqlite::types::Value {
+        match self {
+            Storage::Text => ts.format("%Y-%m-%d %H:%M:%S%.6f").to_string().into(),
+            Storage::Compact => ts.timestamp_micros().into(),
+        }
+    }
+    fn level_value(&self, level: log::Level) -> rusqlite::types::Value {
+        match self {
+            Storage::Text => level.as_str().to_string().into(),
+            Storage::Compact => (level as i64).into(),
+        }
+    }
+}
+
+impl std::str::FromStr for Storage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Storage> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Storage::Text),
+            "compact" => Ok(Storage::Compact),
+            _ => Err(anyhow!(
+                "unknown storage {:?}, expected one of: text, compact",
+                s
+            )),
+        }
+    }
+}
+
+impl IdStrategy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IdStrategy::UuidV4 => "uuid_v4",
+            IdStrategy::UuidV7 => "uuid_v7",
+            IdStrategy::Ulid => "ulid",
+            IdStrategy::Rowid => "rowid",
+        }
+    }
+}
+
+impl std::str::FromStr for IdStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<IdStrategy> {
+        match s.to_ascii_lowercase().as_str() {
+            "uuid_v4" => Ok(IdStrategy::UuidV4),
+            "uuid_v7" => Ok(IdStrategy::UuidV7),
+            "ulid" => Ok(IdStrategy::Ulid),
+            "rowid" => Ok(IdStrategy::Rowid),
+            _ => Err(anyhow!(
+                "unknown id strategy {:?}, expected one of: uuid_v4, uuid_v7, ulid, rowid",
+                s
+            )),
+        }
+    }
+}
+
+impl IdGenerator {
+    fn new(strategy: IdStrategy) -> IdGenerator {
+        IdGenerator {
+            strategy,
+            ulid: Mutex::new(ulid::Generator::new()),
+        }
+    }
+    fn next(&self) -> anyhow::Result<Option<String>> {
+        let id = match self.strategy {
+            IdStrategy::UuidV4 => uuid::Uuid::new_v4().to_string(),
+            IdStrategy::UuidV7 => uuid::Uuid::now_v7().to_string(),
+            IdStrategy::Ulid => {
+                let mut generator = self
+                    .ulid
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                // The random part overflows only after 2^80 ids in the same millisecond.
+                generator
+                    .generate()
+                    .unwrap_or_else(|_| ulid::Ulid::new())
+                    .to_string()
+            }
+            IdStrategy::Rowid => return Ok(None),
+        };
+        Ok(Some(id))
+    }
 }
 
 impl SqliteLogAppender {
-    pub fn new(buf_size: usize, file_name: &str) -> anyhow::Result<SqliteLogAppender> {
+    /// Creates a builder with default options; only the path has to be set.
+    pub fn builder() -> SqliteLogAppenderBuilder {
+        SqliteLogAppenderBuilder::default()
+    }
+    /// Returns the counters of written, retried, dropped and forwarded entries.
+    pub fn stats(&self) -> Stats {
+        self.stats.get()
+    }
+    /// Returns a handle to the same counters, which can be kept after the
+    /// appender is moved into the log4rs config.
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle(self.stats.clone())
+    }
+    fn mdc_json(&self) -> anyhow::Result<Option<String>> {
+        if !self.options.mdc_all {
+            return Ok(None);
+        }
+        let mut entries = serde_json::Map::new();
+        log_mdc::iter(|k, v| {
+            if !self.options.mdc_keys.iter().any(|key| key == k) {
+                entries.insert(k.to_string(), serde_json::Value::from(v));
+            }
+        });
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::to_string(&entries)?))
+    }
+    fn format(&self, record: &log::Record) -> anyhow::Result<Option<String>> {
+        let encoder = match &self.options.encoder {
+            Some(encoder) => encoder,
+            None => return Ok(None),
+        };
+        let mut writer = log4rs::encode::writer::simple::SimpleWriter(Vec::new());
+        encoder.encode(&mut writer, record)?;
+        Ok(Some(String::from_utf8_lossy(&writer.0).into_owned()))
+    }
+    fn try_append(&self, record: &log::Record) -> anyhow::Result<()> {
+        let lr = LogRecord {
+            id: self.ids.next()?,
+            log_level: record.level(),
+            level: self.options.storage.level_value(record.level()),
+            ts: self.options.storage.ts_value(chrono::Utc::now()),
+            message: record.args().to_string(),
+            target: record.target().to_string(),
+            module_path: record.module_path().map(|s| s.to_string()),
+            file: record.file().map(|s| s.to_string()),
+            line: record.line(),
+            thread_name: if self.options.capture_thread_name {
+                thread::current().name().map(|s| s.to_string())
+            } else {
+                None
+            },
+            thread_id: self.options.capture_thread_id.then(thread_id::get),
+            mdc: self
+                .options
+                .mdc_keys
+                .iter()
+                .map(|k| log_mdc::get(k, |v| v.map(|v| v.to_string())))
+                .collect(),
+            mdc_json: self.mdc_json()?,
+            formatted: self.format(record)?,
+            #[cfg(feature = "kv")]
+            kv: kv::key_values(record)?,
+        };
+        match &self.writer {
+            Writer::Caller { sink, .. } => try_lock_sink(sink)?.push(lr),
+            Writer::Thread(thread) => thread.append(lr),
+        }
+    }
+    /// Writes out the buffered entries, returning the error instead of
+    /// reporting it like [`Append::flush`](log4rs::append::Append::flush) does.
+    /// In `background` mode the writer thread retries a failed batch before
+    /// returning, otherwise a single attempt is made.
+    pub fn try_flush(&self) -> anyhow::Result<()> {
+        match &self.writer {
+            Writer::Caller { sink, .. } => try_lock_sink(sink)?.flush_buf(),
+            Writer::Thread(thread) => thread.flush(),
+        }
+    }
+    fn new(options: Options) -> anyhow::Result<SqliteLogAppender> {
+        options.validate()?;
+        let ids = Arc::new(IdGenerator::new(options.id_strategy));
+        let stats = Arc::new(stats::Counters::default());
+        let sink = Sink::new(options.clone(), ids.clone(), stats.clone())?;
+        let writer = if options.background {
+            Writer::Thread(WriterThread::spawn(sink)?)
+        } else {
+            let sink = Arc::new(Mutex::new(sink));
+            let timer = match options.flush_interval {
+                Some(interval) => Some(FlushTimer::spawn(
+                    sink.clone(),
+                    interval,
+                    options.on_error.clone(),
+                )?),
+                None => None,
+            };
+            Writer::Caller {
+                sink,
+                _timer: timer,
+            }
+        };
         Ok(SqliteLogAppender {
-            buf: Arc::new(RwLock::new(Vec::new())),
-            buf_size,
-            file_name: file_name.to_string(),
+            writer,
+            options,
+            ids,
+            stats,
         })
     }
-    fn create_entry_table_if_not_exists(conn: &rusqlite::Connection) -> anyhow::Result<()> {
-        let table_sql = "create table if not exists entry (
-            id varchar(128) not null primary key,
-            ts varchar(128) not null,
-            level varchar(128) not null,
-            message varchar(8192) not null
-        )";
-        let index_ts_sql = "create index if not exists entry_ts_i on entry (ts)";
-        conn.execute(table_sql, [])?;
-        conn.execute(index_ts_sql, [])?;
+}
+
+impl SqliteLogAppenderBuilder {
+    /// Path to the SQLite database file.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> SqliteLogAppenderBuilder {
+        self.options.path = path.into();
+        self
+    }
+    /// Number of entries buffered before they're written to the database.
+    pub fn buffer_size(mut self, buffer_size: usize) -> SqliteLogAppenderBuilder {
+        self.options.buffer_size = buffer_size;
+        self
+    }
+    /// Maximum age of buffered entries before the buffer is written out.
+    pub fn flush_interval(mut self, flush_interval: Duration) -> SqliteLogAppenderBuilder {
+        self.options.flush_interval = Some(flush_interval);
+        self
+    }
+    /// Name of the table the entries are written to.
+    pub fn table(mut self, table: &str) -> SqliteLogAppenderBuilder {
+        self.options.table = table.to_string();
+        self
+    }
+    /// SQLite journal mode, the SQLite default if not set.
+    pub fn journal_mode(mut self, journal_mode: JournalMode) -> SqliteLogAppenderBuilder {
+        self.options.journal_mode = Some(journal_mode);
+        self
+    }
+    /// SQLite synchronous mode, the SQLite default if not set.
+    pub fn synchronous(mut self, synchronous: Synchronous) -> SqliteLogAppenderBuilder {
+        self.options.synchronous = Some(synchronous);
+        self
+    }
+    /// How long to wait for a lock held by another connection, 5s by default.
+    pub fn busy_timeout(mut self, busy_timeout: Duration) -> SqliteLogAppenderBuilder {
+        self.options.busy_timeout = Some(busy_timeout);
+        self
+    }
+    /// Write to the database from a dedicated writer thread.
+    pub fn background(mut self, background: bool) -> SqliteLogAppenderBuilder {
+        self.options.background = background;
+        self
+    }
+    /// Save the name of the thread that logged the entry.
+    pub fn capture_thread_name(mut self, capture: bool) -> SqliteLogAppenderBuilder {
+        self.options.capture_thread_name = capture;
+        self
+    }
+    /// Save the id of the thread that logged the entry, as in log4rs `{I}` pattern.
+    pub fn capture_thread_id(mut self, capture: bool) -> SqliteLogAppenderBuilder {
+        self.options.capture_thread_id = capture;
+        self
+    }
+    /// Save the id of the process that logged the entry.
+    pub fn capture_process_id(mut self, capture: bool) -> SqliteLogAppenderBuilder {
+        self.options.capture_process_id = capture;
+        self
+    }
+    /// Save the name of the host the process runs on.
+    pub fn capture_hostname(mut self, capture: bool) -> SqliteLogAppenderBuilder {
+        self.options.capture_hostname = capture;
+        self
+    }
+    /// MDC keys saved in dedicated `mdc_<key>` columns.
+    pub fn mdc_keys<I, S>(mut self, keys: I) -> SqliteLogAppenderBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.mdc_keys = keys.into_iter().map(|k| k.into()).collect();
+        self
+    }
+    /// Save MDC entries not listed in `mdc_keys` as a JSON object in the `mdc` column.
+    pub fn mdc_all(mut self, mdc_all: bool) -> SqliteLogAppenderBuilder {
+        self.options.mdc_all = mdc_all;
+        self
+    }
+    /// Encoder used to fill the `formatted` column.
+    pub fn encoder(mut self, encoder: Box<dyn log4rs::encode::Encode>) -> SqliteLogAppenderBuilder {
+        self.options.encoder = Some(Arc::from(encoder));
+        self
+    }
+    /// Name of the column a field is saved in, by default the same as the field name.
+    pub fn column(mut self, field: Field, column: &str) -> SqliteLogAppenderBuilder {
+        self.options.columns.push((field, column.to_string()));
+        self
+    }
+    /// Create or upgrade the table when connecting, enabled by default. When
+    /// disabled, the table must already exist and have the `id`, `ts`, `level`
+    /// and `message` columns, and those set with [`column`](Self::column);
+    /// other fields are saved only if their columns exist.
+    pub fn create_schema(mut self, create_schema: bool) -> SqliteLogAppenderBuilder {
+        self.options.create_schema = create_schema;
+        self
+    }
+    /// How timestamps and levels are stored, [`Storage::Text`] by default.
+    pub fn storage(mut self, storage: Storage) -> SqliteLogAppenderBuilder {
+        self.options.storage = storage;
+        self
+    }
+    /// How the ids of the entries are generated, [`IdStrategy::UuidV4`] by default.
+    pub fn id_strategy(mut self, id_strategy: IdStrategy) -> SqliteLogAppenderBuilder {
+        self.options.id_strategy = id_strategy;
+        self
+    }
+    /// Delete the entries older than that.
+    pub fn max_age(mut self, max_age: Duration) -> SqliteLogAppenderBuilder {
+        self.options.retention.max_age = Some(max_age);
+        self
+    }
+    /// Delete the oldest entries when the table has more rows than that.
+    pub fn max_rows(mut self, max_rows: u64) -> SqliteLogAppenderBuilder {
+        self.options.retention.max_rows = Some(max_rows);
+        self
+    }
+    /// Delete the oldest entries when the DB uses more bytes than that.
+    pub fn max_db_size(mut self, max_db_size: u64) -> SqliteLogAppenderBuilder {
+        self.options.retention.max_db_size = Some(max_db_size);
+        self
+    }
+    /// How often the retention limits are checked, every minute by default.
+    pub fn retention_check_interval(mut self, interval: Duration) -> SqliteLogAppenderBuilder {
+        self.options.retention.check_interval = interval;
+        self
+    }
+    /// Number of entries deleted in one transaction, `1000` by default.
+    pub fn retention_batch_size(mut self, batch_size: u64) -> SqliteLogAppenderBuilder {
+        self.options.retention.batch_size = batch_size;
+        self
+    }
+    /// Enable SQLite incremental vacuum, so that the space freed by deleted
+    /// entries is returned to the file system.
+    pub fn incremental_vacuum(mut self, incremental_vacuum: bool) -> SqliteLogAppenderBuilder {
+        self.options.retention.incremental_vacuum = incremental_vacuum;
+        self
+    }
+    /// Maximum number of buffered entries, by default 16 times the `buffer_size`.
+    pub fn max_buffer_size(mut self, max_buffer_size: usize) -> SqliteLogAppenderBuilder {
+        self.options.max_buffer_size = Some(max_buffer_size);
+        self
+    }
+    /// What to do when the buffer is full, [`OverflowPolicy::DropOldest`] by default.
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> SqliteLogAppenderBuilder {
+        self.options.overflow_policy = overflow_policy;
+        self
+    }
+    /// Appender the entries are forwarded to when they can't be written to
+    /// the DB, e.g. a file or console appender.
+    pub fn fallback(
+        mut self,
+        fallback: Box<dyn log4rs::append::Append>,
+    ) -> SqliteLogAppenderBuilder {
+        self.options.fallback = Some(Arc::from(fallback));
+        self
+    }
+    /// How many times a batch is retried after a transient error, i.e. the DB
+    /// is busy or locked, or an I/O error, 3 by default.
+    pub fn max_retries(mut self, max_retries: u32) -> SqliteLogAppenderBuilder {
+        self.options.max_retries = max_retries;
+        self
+    }
+    /// Delay before the first retry, doubled for each next one, 50ms by default.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> SqliteLogAppenderBuilder {
+        self.options.retry_backoff = retry_backoff;
+        self
+    }
+    /// Callback receiving the errors of the appender instead of stderr,
+    /// including the ones of `append` and `flush`, which are not returned to
+    /// log4rs then.
+    pub fn on_error<F>(mut self, on_error: F) -> SqliteLogAppenderBuilder
+    where
+        F: Fn(&anyhow::Error) + Send + Sync + 'static,
+    {
+        self.options.on_error = ErrorHandler(Some(Arc::new(on_error)));
+        self
+    }
+    /// Write up to as many entries with one insert statement as the SQLite
+    /// parameter limit allows, instead of one by one, `true` by default.
+    pub fn multi_row_insert(mut self, multi_row_insert: bool) -> SqliteLogAppenderBuilder {
+        self.options.multi_row_insert = multi_row_insert;
+        self
+    }
+    /// Keep an FTS5 full-text index of the messages, see [`LogReader::search`].
+    pub fn full_text_search(mut self, full_text_search: bool) -> SqliteLogAppenderBuilder {
+        self.options.full_text_search = full_text_search;
+        self
+    }
+    /// Roll the DB file over when the trigger fires: close it, pass it to the
+    /// log4rs roller, e.g. `FixedWindowRoller`, and start a new one.
+    pub fn rolling(
+        mut self,
+        trigger: RollTrigger,
+        roller: Box<dyn log4rs::append::rolling_file::policy::compound::roll::Roll>,
+    ) -> SqliteLogAppenderBuilder {
+        self.options.rolling = Some(rolling::Rolling {
+            trigger,
+            roller: Arc::from(roller),
+        });
+        self
+    }
+    /// Creates the appender, failing if the options are invalid, or if the DB
+    /// can't be opened or its table can't be created or used.
+    pub fn build(self) -> anyhow::Result<SqliteLogAppender> {
+        SqliteLogAppender::new(self.options)
+    }
+}
+
+impl Sink {
+    fn new(
+        options: Options,
+        ids: Arc<IdGenerator>,
+        stats: Arc<stats::Counters>,
+    ) -> anyhow::Result<Sink> {
+        let process_id = options.capture_process_id.then(std::process::id);
+        let hostname = if options.capture_hostname {
+            Some(hostname::get()?.to_string_lossy().into_owned())
+        } else {
+            None
+        };
+        let mut sink = Sink {
+            buf: VecDeque::new(),
+            buf_since: None,
+            retention_checked: None,
+            next_roll_time: None,
+            conn: None,
+            insert_fields: Vec::new(),
+            insert_rows: 1,
+            insert_rows_sql: String::new(),
+            insert_sql: String::new(),
+            #[cfg(feature = "kv")]
+            write_kv: false,
+            process_id,
+            hostname,
+            ids,
+            stats,
+            failures: 0,
+            next_attempt: None,
+            batch_failed: false,
+            stopping: Arc::new(AtomicBool::new(false)),
+            shutdown_deadline: None,
+            options,
+        };
+        sink.next_roll_time = sink
+            .options
+            .rolling
+            .as_ref()
+            .and_then(|r| r.next_roll_time());
+        let conn = sink.connect()?;
+        let existing = schema::table_columns(&conn, &sink.options.table)?;
+        sink.insert_fields = Field::ALL
+            .into_iter()
+            .filter(|f| {
+                let column = sink.options.column(*f);
+                existing.iter().any(|c| c.eq_ignore_ascii_case(column))
+                    && !(*f == Field::Id && sink.options.id_strategy == IdStrategy::Rowid)
+            })
+            .collect();
+        if sink.options.multi_row_insert {
+            let params = sink.insert_fields.len() + sink.options.mdc_keys.len();
+            let limit = conn.limit(rusqlite::limits::Limit::SQLITE_LIMIT_VARIABLE_NUMBER);
+            sink.insert_rows = (limit.max(1) as usize / params.max(1)).max(1);
+        }
+        sink.insert_rows_sql = sink.insert_sql(sink.insert_rows);
+        sink.insert_sql = sink.insert_sql(1);
+        #[cfg(feature = "kv")]
+        {
+            sink.write_kv = schema::table_exists(&conn, &format!("{}_kv", sink.options.table))?;
+        }
+        sink.conn = Some(conn);
+        Ok(sink)
+    }
+    fn insert_sql(&self, rows: usize) -> String {
+        let columns = self
+            .insert_fields
+            .iter()
+            .map(|f| self.options.column(*f).to_string())
+            .chain(self.options.mdc_columns())
+            .collect::<Vec<String>>();
+        let row = format!("({})", vec!["?"; columns.len()].join(", "));
+        format!(
+            "insert into {} ({}) values {}",
+            self.options.table,
+            columns.join(", "),
+            vec![row; rows].join(", ")
+        )
+    }
+    fn prepare_schema(&self, conn: &mut rusqlite::Connection) -> anyhow::Result<()> {
+        let table = &self.options.table;
+        if self.options.create_schema {
+            schema::migrate(conn, &self.options)?;
+            #[cfg(feature = "kv")]
+            kv::create_table_if_not_exists(conn, table)?;
+            // Column names and MDC keys depend on the configuration, so their
+            // columns are added to tables of the current version as well.
+            let columns = schema::record_detail_columns(&self.options)
+                .into_iter()
+                .chain(
+                    self.options
+                        .mdc_columns()
+                        .into_iter()
+                        .map(|c| (c, "varchar(1024)")),
+                )
+                .collect::<Vec<(String, &str)>>();
+            schema::add_missing_columns(conn, table, &columns)?;
+        } else if !schema::table_exists(conn, table)? {
+            return Err(anyhow!("Table `{}` does not exist", table));
+        }
+        let ts_column = self.options.column(Field::Ts);
+        let stored = match schema::column_type(conn, table, ts_column)? {
+            Some(decl) if decl.to_ascii_lowercase().contains("int") => Storage::Compact,
+            Some(_) => Storage::Text,
+            None => return Err(anyhow!("Table `{}` has no column `{}`", table, ts_column)),
+        };
+        if stored != self.options.storage {
+            return Err(anyhow!(
+                "Table `{}` uses `{}` storage, but the appender is configured with `{}` storage",
+                table,
+                stored.as_str(),
+                self.options.storage.as_str()
+            ));
+        }
+        let id_column = self.options.column(Field::Id);
+        let integer_ids = match schema::column_type(conn, table, id_column)? {
+            Some(decl) => decl.to_ascii_lowercase().contains("int"),
+            None => return Err(anyhow!("Table `{}` has no column `{}`", table, id_column)),
+        };
+        if integer_ids != (self.options.id_strategy == IdStrategy::Rowid) {
+            return Err(anyhow!(
+                "Table `{}` has {} ids, which can't be used with `{}` id strategy",
+                table,
+                if integer_ids { "integer" } else { "text" },
+                self.options.id_strategy.as_str()
+            ));
+        }
+        if self.options.create_schema && self.options.storage == Storage::Compact {
+            schema::create_compact_view(conn, &self.options)?;
+        }
+        if self.options.full_text_search {
+            schema::create_full_text_index(conn, &self.options)?;
+        }
+        let existing = schema::table_columns(conn, table)?;
+        // Fields mapped to a column are expected to be saved.
+        let required = Field::ALL
+            .iter()
+            .filter(|f| f.is_required() || self.options.columns.iter().any(|(g, _)| g == *f))
+            .map(|f| self.options.column(*f).to_string())
+            .chain(self.options.mdc_columns());
+        for column in required {
+            if !existing.iter().any(|c| c.eq_ignore_ascii_case(&column)) {
+                return Err(anyhow!("Table `{}` has no column `{}`", table, column));
+            }
+        }
+        Ok(())
+    }
+    fn configure(&self, conn: &rusqlite::Connection) -> anyhow::Result<()> {
+        if let Some(busy_timeout) = self.options.busy_timeout {
+            conn.busy_timeout(busy_timeout)?;
+        }
+        if let Some(journal_mode) = self.options.journal_mode {
+            conn.pragma_update_and_check(None, "journal_mode", journal_mode.as_str(), |_| Ok(()))?;
+        }
+        if let Some(synchronous) = self.options.synchronous {
+            conn.pragma_update(None, "synchronous", synchronous.as_str())?;
+        }
+        if self.options.retention.incremental_vacuum {
+            // Takes effect only for new DBs, or after a `VACUUM`.
+            conn.pragma_update(None, "auto_vacuum", "incremental")?;
+        }
         Ok(())
     }
     fn connect(&self) -> anyhow::Result<rusqlite::Connection> {
-        let conn = rusqlite::Connection::open(&self.file_name)?;
-        SqliteLogAppender::create_entry_table_if_not_exists(&conn)?;
+        let mut conn = rusqlite::Connection::open(&self.options.path)?;
+        self.configure(&conn)?;
+        self.prepare_schema(&mut conn)?;
         Ok(conn)
     }
-    fn maybe_flush_buf(&self, buf_lock: &mut Vec<LogRecord>) -> anyhow::Result<()> {
-        if buf_lock.len() < self.buf_size {
+    fn push(&mut self, lr: LogRecord) -> anyhow::Result<()> {
+        if self.buf.len() >= self.options.max_buffer_size() {
+            match self.options.overflow_policy {
+                OverflowPolicy::Block => self.flush_until_not_full(),
+                policy => {
+                    self.stats.count_dropped();
+                    if !make_room(&mut self.buf, policy, lr.log_level) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            self.buf_since = Some(Instant::now());
+        }
+        self.buf.push_back(lr);
+        self.maybe_flush_buf()
+    }
+    fn flush_until_not_full(&mut self) {
+        let mut delay = Duration::from_millis(10);
+        while self.buf.len() >= self.options.max_buffer_size() {
+            if let Err(e) = self.flush_buf() {
+                if delay == Duration::from_millis(10) {
+                    self.options
+                        .on_error
+                        .handle(&e.context("Buffer full, waiting until it's written out"));
+                }
+                // Once the appender is dropped, the oldest entries are dropped
+                // after a while, so that the drop doesn't hang when the DB
+                // doesn't recover.
+                if self.stopping.load(Ordering::Relaxed) {
+                    let deadline = *self
+                        .shutdown_deadline
+                        .get_or_insert_with(|| Instant::now() + SHUTDOWN_TIMEOUT);
+                    if Instant::now() >= deadline {
+                        self.buf.pop_front();
+                        self.stats.count_dropped();
+                        return;
+                    }
+                }
+                thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(1));
+            }
+        }
+    }
+    fn dropped_record(&self, dropped: u64) -> anyhow::Result<LogRecord> {
+        let level = log::Level::Warn;
+        Ok(LogRecord {
+            id: self.ids.next()?,
+            log_level: level,
+            level: self.options.storage.level_value(level),
+            ts: self.options.storage.ts_value(chrono::Utc::now()),
+            message: format!(
+                "{} log entries dropped because the buffer was full",
+                dropped
+            ),
+            target: "x_log4rs_sqlite".to_string(),
+            module_path: None,
+            file: None,
+            line: None,
+            thread_name: None,
+            thread_id: None,
+            mdc: vec![None; self.options.mdc_keys.len()],
+            mdc_json: None,
+            formatted: None,
+            #[cfg(feature = "kv")]
+            kv: Vec::new(),
+        })
+    }
+    // Time until the buffer is to be written out, which is after the backoff
+    // when the last attempt failed.
+    fn flush_due_in(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let due = if self.failures > 0 || self.buf.len() >= self.options.buffer_size {
+            Some(now)
+        } else {
+            match (self.options.flush_interval, self.buf_since) {
+                (Some(interval), Some(since)) => Some(since + interval),
+                _ => None,
+            }
+        };
+        let due = match (due, self.next_attempt) {
+            (Some(due), Some(next_attempt)) => Some(due.max(next_attempt)),
+            (due, _) => due,
+        };
+        due.map(|due| due.saturating_duration_since(now))
+    }
+    fn is_flush_due(&self) -> bool {
+        self.flush_due_in() == Some(Duration::ZERO)
+    }
+    fn is_retry_pending(&self) -> bool {
+        self.failures > 0
+    }
+    // Errors of the batches that are going to be retried are not reported.
+    fn maybe_flush_buf(&mut self) -> anyhow::Result<()> {
+        if !self.is_flush_due() {
             return Ok(());
         }
-        self.flush_buf(buf_lock)?;
+        match self.flush_buf() {
+            Err(_) if self.is_retry_pending() => Ok(()),
+            result => result,
+        }
+    }
+    // Waits for the retries of a failed batch, so it's only used where that
+    // doesn't hold up the threads that log: by the writer thread, and when the
+    // appender is dropped.
+    fn flush_with_retries(&mut self) -> anyhow::Result<()> {
+        loop {
+            let result = self.flush_buf();
+            match self.next_attempt {
+                Some(next_attempt) if result.is_err() && self.is_retry_pending() => {
+                    thread::sleep(next_attempt.saturating_duration_since(Instant::now()));
+                }
+                _ => return result,
+            }
+        }
+    }
+    // Makes a single attempt to write out the buffer, the entries are
+    // forwarded to the fallback appender only once the retries are used up.
+    fn flush_buf(&mut self) -> anyhow::Result<()> {
+        let result = self.write_buf();
+        let fallback = match (&result, &self.options.fallback) {
+            (Err(_), Some(fallback)) if !self.is_retry_pending() => fallback.clone(),
+            _ => return result,
+        };
+        if let Err(e) = result {
+            self.options.on_error.handle(
+                &e.context("Error writing entries, forwarding them to the fallback appender"),
+            );
+        }
+        for lr in std::mem::take(&mut self.buf) {
+            match self.forward(fallback.as_ref(), &lr) {
+                Ok(()) => stats::Counters::add(&self.stats.forwarded, 1),
+                Err(e) => self
+                    .options
+                    .on_error
+                    .handle(&e.context("Error forwarding entry to the fallback appender")),
+            }
+        }
+        self.buf_since = None;
+        self.batch_failed = false;
+        fallback.flush();
         Ok(())
     }
-    fn flush_buf(&self, buf_lock: &mut Vec<LogRecord>) -> anyhow::Result<()> {
-        let mut conn = self.connect()?;
-        let tx = conn.transaction()?;
-        {
-            let mut stmt =
-                tx.prepare("insert into entry (id, ts, level, message) values (?1, ?2, ?3, ?4)")?;
-            for lr in buf_lock.iter() {
-                stmt.execute([&lr.id, &lr.ts, &lr.level, &lr.message])?;
+    // The MDC of the entry is set while it's forwarded, so that the fallback
+    // appender encoder sees it.
+    fn forward(&self, fallback: &dyn log4rs::append::Append, lr: &LogRecord) -> anyhow::Result<()> {
+        let mut mdc = self
+            .options
+            .mdc_keys
+            .iter()
+            .zip(lr.mdc.iter())
+            .filter_map(|(k, v)| Some((k.clone(), v.clone()?)))
+            .collect::<Vec<(String, String)>>();
+        if let Some(json) = &lr.mdc_json {
+            let entries: serde_json::Map<String, serde_json::Value> = serde_json::from_str(json)?;
+            mdc.extend(entries.into_iter().map(|(k, v)| match v {
+                serde_json::Value::String(v) => (k, v),
+                v => (k, v.to_string()),
+            }));
+        }
+        let _mdc = log_mdc::extend_scoped(mdc);
+        #[cfg(feature = "kv")]
+        let kv = kv::Pairs(&lr.kv);
+        let mut builder = log::Record::builder();
+        builder
+            .level(lr.log_level)
+            .target(&lr.target)
+            .module_path(lr.module_path.as_deref())
+            .file(lr.file.as_deref())
+            .line(lr.line);
+        #[cfg(feature = "kv")]
+        builder.key_values(&kv);
+        fallback.append(&builder.args(format_args!("{}", lr.message)).build())
+    }
+    fn write_buf(&mut self) -> anyhow::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.buf_since = Some(Instant::now());
+        if self.is_roll_due() {
+            // Better to keep writing to the current file than to lose the entries.
+            if let Err(e) = self.roll() {
+                self.options
+                    .on_error
+                    .handle(&e.context("Error rolling over DB file"));
             }
         }
-        tx.commit()?;
-        buf_lock.clear();
+        if self.is_retry_pending() {
+            stats::Counters::add(&self.stats.retries, 1);
+        }
+        let mut conn = match self.try_insert() {
+            Ok(conn) => conn,
+            Err(e) => {
+                // After the last retry, or an error that isn't transient, the
+                // next attempt starts over, but not before the longest backoff,
+                // so that the threads that log don't wait for the DB with each
+                // entry.
+                let retry = self.failures < self.options.max_retries && is_transient_error(&e);
+                let exponent = if retry {
+                    self.failures
+                } else {
+                    self.options.max_retries
+                };
+                let backoff = self
+                    .options
+                    .retry_backoff
+                    .saturating_mul(1 << exponent.min(16));
+                if retry {
+                    self.failures += 1;
+                } else {
+                    self.failures = 0;
+                    if !self.batch_failed {
+                        self.batch_failed = true;
+                        stats::Counters::add(&self.stats.failed_batches, 1);
+                    }
+                }
+                self.next_attempt = Some(Instant::now() + backoff);
+                return Err(e);
+            }
+        };
+        self.failures = 0;
+        self.next_attempt = None;
+        self.batch_failed = false;
+        stats::Counters::add(&self.stats.written, self.buf.len() as u64);
+        self.buf.clear();
+        self.buf_since = None;
+        let dropped = self.stats.take_unreported_dropped();
+        if dropped > 0 {
+            // Written right away, or with the next batch if that fails.
+            self.buf.push_back(self.dropped_record(dropped)?);
+            if let Err(e) = self.insert(&mut conn) {
+                self.buf_since = Some(Instant::now());
+                self.options.on_error.handle(&e.into());
+            } else {
+                stats::Counters::add(&self.stats.written, 1);
+                self.buf.clear();
+            }
+        }
+        if self.is_retention_due() {
+            self.retention_checked = Some(Instant::now());
+            // The entries are already written, so a failure here is only reported.
+            if let Err(e) = retention::enforce(&mut conn, &self.options) {
+                self.options
+                    .on_error
+                    .handle(&e.context("Error deleting old entries"));
+            }
+        }
+        self.conn = Some(conn);
         Ok(())
     }
+    // Returns the connection the buffer was written with.
+    fn try_insert(&mut self) -> anyhow::Result<rusqlite::Connection> {
+        let mut conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => self.connect()?,
+        };
+        if let Err(e) = self.insert(&mut conn) {
+            if !is_connection_error(&e) {
+                self.conn = Some(conn);
+            }
+            return Err(e.into());
+        }
+        Ok(conn)
+    }
+    fn is_roll_due(&self) -> bool {
+        self.options
+            .rolling
+            .as_ref()
+            .is_some_and(|r| r.is_due(&self.options.path, self.next_roll_time))
+    }
+    fn roll(&mut self) -> anyhow::Result<()> {
+        let rolling = match &self.options.rolling {
+            Some(rolling) => rolling.clone(),
+            None => return Ok(()),
+        };
+        self.next_roll_time = rolling.next_roll_time();
+        if let Some(conn) = self.conn.take() {
+            conn.close().map_err(|(_, e)| e)?;
+        }
+        rolling.roller.roll(&self.options.path)?;
+        self.conn = Some(self.connect()?);
+        Ok(())
+    }
+    fn is_retention_due(&self) -> bool {
+        let retention = &self.options.retention;
+        retention.is_enabled()
+            && self
+                .retention_checked
+                .is_none_or(|t| t.elapsed() >= retention.check_interval)
+    }
+    fn insert(&self, conn: &mut rusqlite::Connection) -> rusqlite::Result<()> {
+        let tx = conn.transaction()?;
+        #[cfg(feature = "kv")]
+        let mut ids = Vec::with_capacity(self.buf.len());
+        let records = self.buf.iter().collect::<Vec<&LogRecord>>();
+        for chunk in records.chunks(self.insert_rows) {
+            let mut stmt = match chunk.len() {
+                n if n == self.insert_rows => tx.prepare_cached(&self.insert_rows_sql)?,
+                1 => tx.prepare_cached(&self.insert_sql)?,
+                n => tx.prepare_cached(&self.insert_sql(n))?,
+            };
+            let mut params = Vec::new();
+            for lr in chunk {
+                params.extend(self.insert_fields.iter().map(|f| self.field_value(lr, *f)));
+                params.extend(lr.mdc.iter().map(|v| v as &dyn rusqlite::ToSql));
+            }
+            stmt.execute(params.as_slice())?;
+            // The rows inserted by one statement get consecutive rowids.
+            #[cfg(feature = "kv")]
+            let first_rowid = tx.last_insert_rowid() - chunk.len() as i64 + 1;
+            #[cfg(feature = "kv")]
+            ids.extend(
+                chunk
+                    .iter()
+                    .zip(first_rowid..)
+                    .map(|(lr, rowid)| match &lr.id {
+                        Some(id) => rusqlite::types::Value::Text(id.clone()),
+                        None => rusqlite::types::Value::Integer(rowid),
+                    }),
+            );
+        }
+        #[cfg(feature = "kv")]
+        if self.write_kv {
+            kv::insert(&tx, &self.options.table, &self.buf, &ids)?;
+        }
+        tx.commit()
+    }
+    fn field_value<'a>(&'a self, lr: &'a LogRecord, field: Field) -> &'a dyn rusqlite::ToSql {
+        match field {
+            Field::Id => &lr.id,
+            Field::Ts => &lr.ts,
+            Field::Level => &lr.level,
+            Field::Message => &lr.message,
+            Field::Target => &lr.target,
+            Field::ModulePath => &lr.module_path,
+            Field::File => &lr.file,
+            Field::Line => &lr.line,
+            Field::ThreadName => &lr.thread_name,
+            Field::ThreadId => &lr.thread_id,
+            Field::ProcessId => &self.process_id,
+            Field::Hostname => &self.hostname,
+            Field::Mdc => &lr.mdc_json,
+            Field::Formatted => &lr.formatted,
+        }
+    }
 }
 
-impl std::fmt::Debug for SqliteLogAppender {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.debug_struct("SqliteLogAppender")
-            .field("buf_size", &self.buf_size)
-            .field("file_name", &self.file_name)
-            .finish()
+// Makes room in a full buffer for a new entry of `level` by dropping one entry
+// as the policy says, returns `false` when it's the new entry that is to be
+// dropped. Not used with `block`, which waits instead.
+fn make_room(buf: &mut VecDeque<LogRecord>, policy: OverflowPolicy, level: log::Level) -> bool {
+    match policy {
+        OverflowPolicy::Block | OverflowPolicy::DropNewest => false,
+        OverflowPolicy::DropOldest => {
+            buf.pop_front();
+            true
+        }
+        OverflowPolicy::DropBelow(min) => {
+            if level > min {
+                return false;
+            }
+            let i = buf.iter().position(|r| r.log_level > min);
+            buf.remove(i.unwrap_or(0));
+            true
+        }
     }
 }
 
-impl log4rs::append::Append for SqliteLogAppender {
-    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
-        let lr = LogRecord {
-            id: uuid::Uuid::new_v4().to_string(),
-            level: record.level().as_str().to_string(),
-            ts: chrono::Utc::now()
-                .format("%Y-%m-%d %H:%M:%S%.6f")
-                .to_string(),
-            message: record.args().to_string(),
-        };
-        let mut buf_lock = self
-            .buf
-            .write()
-            .map_err(|e| anyhow!("Error locking buf: {}", e))?;
-        buf_lock.push(lr);
-        self.maybe_flush_buf(&mut buf_lock)?;
+impl Queue {
+    // The state is only modified by infallible operations, so it's usable
+    // after a panic.
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        // The threads waiting for a flush get an error.
+        state.flushes.clear();
+        self.added.notify_all();
+        self.taken.notify_all();
+    }
+}
+
+// Closes the queue when the writer thread exits, also by a panic, so that the
+// threads that log or flush don't wait for it.
+struct CloseQueue(Arc<Queue>);
+
+impl Drop for CloseQueue {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl WriterThread {
+    fn spawn(sink: Sink) -> anyhow::Result<WriterThread> {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState::default()),
+            capacity: sink.options.max_buffer_size(),
+            added: Condvar::new(),
+            taken: Condvar::new(),
+        });
+        let overflow_policy = sink.options.overflow_policy;
+        let stats = sink.stats.clone();
+        let stopping = sink.stopping.clone();
+        let on_error = sink.options.on_error.clone();
+        let handle = thread::Builder::new()
+            .name("x-log4rs-sqlite".to_string())
+            .spawn({
+                let queue = queue.clone();
+                move || WriterThread::run(sink, queue)
+            })?;
+        Ok(WriterThread {
+            queue,
+            handle: Some(handle),
+            overflow_policy,
+            stats,
+            stopping,
+            on_error,
+        })
+    }
+    fn run(mut sink: Sink, queue: Arc<Queue>) {
+        IS_WRITER_THREAD.set(true);
+        let _close = CloseQueue(queue.clone());
+        loop {
+            let (entries, flushes, closed) = {
+                let mut state = queue.lock();
+                let due = sink.flush_due_in().map(|d| Instant::now() + d);
+                while state.entries.is_empty() && state.flushes.is_empty() && !state.closed {
+                    state = match due {
+                        Some(due) => {
+                            let timeout = due.saturating_duration_since(Instant::now());
+                            if timeout.is_zero() {
+                                break;
+                            }
+                            queue
+                                .added
+                                .wait_timeout(state, timeout)
+                                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                                .0
+                        }
+                        None => queue
+                            .added
+                            .wait(state)
+                            .unwrap_or_else(std::sync::PoisonError::into_inner),
+                    };
+                }
+                (
+                    std::mem::take(&mut state.entries),
+                    std::mem::take(&mut state.flushes),
+                    state.closed,
+                )
+            };
+            queue.taken.notify_all();
+            let timed_out = entries.is_empty() && flushes.is_empty();
+            for lr in entries {
+                if let Err(e) = sink.push(lr) {
+                    sink.options.on_error.handle(&e);
+                }
+            }
+            for reply in flushes {
+                let _ = reply.send(sink.flush_with_retries());
+            }
+            if timed_out {
+                if let Err(e) = sink.maybe_flush_buf() {
+                    sink.options.on_error.handle(&e);
+                }
+            }
+            if closed {
+                break;
+            }
+        }
+        if let Err(e) = sink.flush_with_retries() {
+            sink.options.on_error.handle(&e);
+        }
+    }
+    // When the queue is full, the `block` policy waits for the writer thread
+    // to take the entries, the other policies drop one as they do with the
+    // buffer.
+    fn append(&self, lr: LogRecord) -> anyhow::Result<()> {
+        let mut state = self.queue.lock();
+        loop {
+            if state.closed {
+                return Err(anyhow!("Writer thread stopped"));
+            }
+            if state.entries.len() < self.queue.capacity {
+                break;
+            }
+            if self.overflow_policy != OverflowPolicy::Block {
+                self.stats.count_dropped();
+                if !make_room(&mut state.entries, self.overflow_policy, lr.log_level) {
+                    return Ok(());
+                }
+                break;
+            }
+            state = self
+                .queue
+                .taken
+                .wait(state)
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+        }
+        state.entries.push_back(lr);
+        self.queue.added.notify_one();
         Ok(())
     }
-    fn flush(&self) {
-        let mut buf_lock = self.buf.write().expect("Error locking buf");
-        self.flush_buf(&mut buf_lock).expect("Error flushing buf");
+    fn flush(&self) -> anyhow::Result<()> {
+        if let Some(handle) = &self.handle {
+            if handle.thread().id() == thread::current().id() {
+                return Err(anyhow!("Cannot flush from the writer thread"));
+            }
+        }
+        let (reply_tx, reply_rx) = mpsc::channel();
+        {
+            let mut state = self.queue.lock();
+            if state.closed {
+                return Err(anyhow!("Writer thread stopped"));
+            }
+            state.flushes.push(reply_tx);
+            self.queue.added.notify_one();
+        }
+        reply_rx
+            .recv()
+            .map_err(|_| anyhow!("Writer thread stopped"))?
+    }
+}
+
+impl Drop for WriterThread {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        self.queue.close();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                self.on_error.handle(&anyhow!("Writer thread panicked"));
+            }
+        }
+    }
+}
+
+impl FlushTimer {
+    fn spawn(
+        sink: Arc<Mutex<Sink>>,
+        interval: Duration,
+        on_error: ErrorHandler,
+    ) -> anyhow::Result<FlushTimer> {
+        let (stop, stop_rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("x-log4rs-sqlite-timer".to_string())
+            .spawn(move || FlushTimer::run(sink, interval, stop_rx))?;
+        Ok(FlushTimer {
+            stop: Some(stop),
+            handle: Some(handle),
+            on_error,
+        })
+    }
+    fn run(sink: Arc<Mutex<Sink>>, interval: Duration, stop_rx: mpsc::Receiver<()>) {
+        let mut timeout = interval;
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(timeout) {
+            let mut buf_lock = lock_sink(&sink);
+            if let Err(e) = buf_lock.maybe_flush_buf() {
+                buf_lock.options.on_error.handle(&e);
+            }
+            timeout = buf_lock.flush_due_in().unwrap_or(interval);
+        }
     }
 }
 
-#[derive(Clone, Debug, Default, serde::Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct SqliteLogAppenderConfig {
-    path: String,
+impl Drop for FlushTimer {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                self.on_error
+                    .handle(&anyhow!("Flush timer thread panicked"));
+            }
+        }
+    }
 }
 
-pub struct SqliteLogAppenderDeserializer {}
+impl std::fmt::Debug for SqliteLogAppender {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SqliteLogAppender")
+            .field("options", &self.options)
+            .finish()
+    }
+}
 
-impl log4rs::config::Deserialize for SqliteLogAppenderDeserializer {
-    type Trait = dyn log4rs::append::Append;
-    type Config = SqliteLogAppenderConfig;
+impl Drop for SqliteLogAppender {
+    fn drop(&mut self) {
+        if let Writer::Caller { sink, .. } = &self.writer {
+            if let Err(e) = lock_sink(sink).flush_with_retries() {
+                self.options.on_error.handle(&e);
+            }
+        }
+    }
+}
 
-    fn deserialize(
-        &self,
-        config: SqliteLogAppenderConfig,
-        _: &log4rs::config::Deserializers,
-    ) -> anyhow::Result<Box<dyn log4rs::append::Append>> {
-        Ok(Box::new(SqliteLogAppender::new(1024, &config.path)?))
+impl log4rs::append::Append for SqliteLogAppender {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        let result = self.try_append(record);
+        match (result, &self.options.on_error.0) {
+            (Err(e), Some(_)) => {
+                self.options.on_error.handle(&e);
+                Ok(())
+            }
+            (result, _) => result,
+        }
+    }
+    fn flush(&self) {
+        if let Err(e) = self.try_flush() {
+            self.options.on_error.handle(&e);
+        }
     }
 }
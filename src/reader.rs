@@ -0,0 +1,569 @@
+use anyhow::anyhow;
+use rusqlite::types::Value;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::schema;
+use crate::Field;
+use crate::Storage;
+
+// Number of entries read from the DB at once by `LogEntries`.
+const PAGE_SIZE: usize = 1000;
+
+/// Reads the entries written by [`SqliteLogAppender`](crate::SqliteLogAppender).
+pub struct LogReader {
+    conn: rusqlite::Connection,
+    table: String,
+    storage: Storage,
+    // Columns of the fields, or `None` for the fields the table has no columns for.
+    columns: Vec<(Field, Option<String>)>,
+    mdc_columns: Vec<String>,
+    snippet_markers: (String, String),
+}
+
+/// Builder for [`LogReader`], see [`LogReader::builder`].
+#[derive(Clone, Debug)]
+pub struct LogReaderBuilder {
+    table: String,
+    columns: Vec<(Field, String)>,
+}
+
+/// Entry read by [`LogReader`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    /// SQLite `rowid` of the entry, increasing in the order the entries are
+    /// written.
+    pub rowid: i64,
+    pub id: String,
+    /// Timestamp at UTC.
+    pub ts: chrono::NaiveDateTime,
+    pub level: log::Level,
+    pub message: String,
+    pub target: Option<String>,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub thread_name: Option<String>,
+    pub thread_id: Option<u64>,
+    pub process_id: Option<u32>,
+    pub hostname: Option<String>,
+    /// MDC entries, both the ones saved in `mdc_<key>` columns and in the `mdc` JSON object.
+    pub mdc: BTreeMap<String, String>,
+    pub formatted: Option<String>,
+}
+
+/// Entry found by [`LogReader::search`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub entry: LogEntry,
+    /// Fragment of the message with the matching terms between the snippet markers.
+    pub snippet: String,
+    /// FTS5 `bm25` rank, the lower the better the match.
+    pub rank: f64,
+}
+
+/// Selects the entries read by [`LogReader::entries`], all of them by default.
+#[derive(Clone, Debug, Default)]
+pub struct LogFilter {
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    min_level: Option<log::Level>,
+    target_prefix: Option<String>,
+    message_contains: Option<String>,
+    after_rowid: Option<i64>,
+    limit: Option<usize>,
+    offset: usize,
+    newest_first: bool,
+}
+
+impl LogFilter {
+    pub fn new() -> LogFilter {
+        LogFilter::default()
+    }
+    /// Only the entries logged at or after that time.
+    pub fn since(mut self, since: chrono::DateTime<chrono::Utc>) -> LogFilter {
+        self.since = Some(since);
+        self
+    }
+    /// Only the entries logged before that time.
+    pub fn until(mut self, until: chrono::DateTime<chrono::Utc>) -> LogFilter {
+        self.until = Some(until);
+        self
+    }
+    /// Only the entries at that level or more severe, e.g. `Warn` selects
+    /// `WARN` and `ERROR` entries.
+    pub fn min_level(mut self, min_level: log::Level) -> LogFilter {
+        self.min_level = Some(min_level);
+        self
+    }
+    /// Only the entries whose target starts with that prefix.
+    pub fn target_prefix(mut self, prefix: &str) -> LogFilter {
+        self.target_prefix = Some(prefix.to_string());
+        self
+    }
+    /// Only the entries whose message contains that text, case sensitive.
+    pub fn message_contains(mut self, text: &str) -> LogFilter {
+        self.message_contains = Some(text.to_string());
+        self
+    }
+    /// Only the entries written after the one with that rowid, see
+    /// [`LogEntry::rowid`] and [`LogReader::max_rowid`].
+    pub fn after_rowid(mut self, rowid: i64) -> LogFilter {
+        self.after_rowid = Some(rowid);
+        self
+    }
+    /// Read at most that many entries.
+    pub fn limit(mut self, limit: usize) -> LogFilter {
+        self.limit = Some(limit);
+        self
+    }
+    /// Skip that many first entries.
+    pub fn offset(mut self, offset: usize) -> LogFilter {
+        self.offset = offset;
+        self
+    }
+    /// Read the newest entries first, the oldest are read first by default.
+    pub fn newest_first(mut self, newest_first: bool) -> LogFilter {
+        self.newest_first = newest_first;
+        self
+    }
+}
+
+/// Iterator over the entries selected by a [`LogFilter`].
+pub struct LogEntries<'a> {
+    reader: &'a LogReader,
+    filter: LogFilter,
+    page: VecDeque<(Value, i64, LogEntry)>,
+    // `ts` and `rowid` of the last entry read, the next page starts after it.
+    last: Option<(Value, i64)>,
+    done: bool,
+}
+
+// Both storages are handled based on the type of the values.
+fn decode_id(value: Value) -> anyhow::Result<String> {
+    match value {
+        Value::Text(id) => Ok(id),
+        Value::Integer(id) => Ok(id.to_string()),
+        value => Err(anyhow!("Invalid id: {:?}", value)),
+    }
+}
+
+fn decode_ts(value: Value) -> anyhow::Result<chrono::NaiveDateTime> {
+    match value {
+        Value::Text(ts) => chrono::NaiveDateTime::parse_from_str(&ts, "%Y-%m-%d %H:%M:%S%.f")
+            .map_err(|e| anyhow!("Invalid timestamp {:?}: {}", ts, e)),
+        Value::Integer(ts) => chrono::DateTime::from_timestamp_micros(ts)
+            .map(|ts| ts.naive_utc())
+            .ok_or_else(|| anyhow!("Invalid timestamp: {}", ts)),
+        value => Err(anyhow!("Invalid timestamp: {:?}", value)),
+    }
+}
+
+fn decode_level(value: Value) -> anyhow::Result<log::Level> {
+    match value {
+        Value::Text(level) => level
+            .parse()
+            .map_err(|_| anyhow!("Invalid level: {:?}", level)),
+        Value::Integer(1) => Ok(log::Level::Error),
+        Value::Integer(2) => Ok(log::Level::Warn),
+        Value::Integer(3) => Ok(log::Level::Info),
+        Value::Integer(4) => Ok(log::Level::Debug),
+        Value::Integer(5) => Ok(log::Level::Trace),
+        value => Err(anyhow!("Invalid level: {:?}", value)),
+    }
+}
+
+fn decode_text(value: Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Text(s) => Some(s),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Real(f) => Some(f.to_string()),
+        Value::Blob(b) => Some(String::from_utf8_lossy(&b).into_owned()),
+    }
+}
+
+fn decode_int<T: TryFrom<i64>>(name: &str, value: Value) -> anyhow::Result<Option<T>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Integer(i) => T::try_from(i)
+            .map(Some)
+            .map_err(|_| anyhow!("Invalid {}: {}", name, i)),
+        value => Err(anyhow!("Invalid {}: {:?}", name, value)),
+    }
+}
+
+impl Default for LogReaderBuilder {
+    fn default() -> LogReaderBuilder {
+        LogReaderBuilder {
+            table: "entry".to_string(),
+            columns: Vec::new(),
+        }
+    }
+}
+
+impl LogReaderBuilder {
+    /// Name of the entry table, `entry` by default.
+    pub fn table(mut self, table: &str) -> LogReaderBuilder {
+        self.table = table.to_string();
+        self
+    }
+    /// Name of the column a field is read from, by default the same as the
+    /// field name; see
+    /// [`SqliteLogAppenderBuilder::column`](crate::SqliteLogAppenderBuilder::column).
+    pub fn column(mut self, field: Field, column: &str) -> LogReaderBuilder {
+        self.columns.push((field, column.to_string()));
+        self
+    }
+    /// Opens the table of the DB for reading.
+    pub fn open<P: AsRef<Path>>(self, path: P) -> anyhow::Result<LogReader> {
+        let table = self.table.as_str();
+        if !crate::is_valid_identifier(table) {
+            return Err(anyhow!(
+                "Invalid `table`: {:?} is not a valid SQL identifier",
+                table
+            ));
+        }
+        for (_, column) in self.columns.iter() {
+            if !crate::is_valid_identifier(column) {
+                return Err(anyhow!(
+                    "Invalid `columns`: {:?} is not a valid SQL identifier",
+                    column
+                ));
+            }
+        }
+        let column_name = |field: Field| {
+            self.columns
+                .iter()
+                .rev()
+                .find(|(f, _)| *f == field)
+                .map(|(_, column)| column.as_str())
+                .unwrap_or(field.name())
+        };
+        let conn = rusqlite::Connection::open_with_flags(
+            path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+                | rusqlite::OpenFlags::SQLITE_OPEN_URI
+                | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        if !schema::table_exists(&conn, table)? {
+            return Err(anyhow!("Table `{}` does not exist", table));
+        }
+        let existing = schema::table_columns(&conn, table)?;
+        let columns = Field::ALL
+            .into_iter()
+            .map(|f| {
+                let name = column_name(f);
+                let column = existing
+                    .iter()
+                    .find(|c| c.eq_ignore_ascii_case(name))
+                    .cloned();
+                match column {
+                    None if f.is_required() => {
+                        Err(anyhow!("Table `{}` has no column `{}`", table, name))
+                    }
+                    column => Ok((f, column)),
+                }
+            })
+            .collect::<anyhow::Result<Vec<(Field, Option<String>)>>>()?;
+        let mdc_columns = existing
+            .into_iter()
+            .filter(|c| c.starts_with("mdc_"))
+            .filter(|c| !columns.iter().any(|(_, f)| f.as_ref() == Some(c)))
+            .collect();
+        let storage = match schema::column_type(&conn, table, column_name(Field::Ts))? {
+            Some(decl) if decl.to_ascii_lowercase().contains("int") => Storage::Compact,
+            _ => Storage::Text,
+        };
+        Ok(LogReader {
+            conn,
+            table: self.table,
+            storage,
+            columns,
+            mdc_columns,
+            snippet_markers: ("[".to_string(), "]".to_string()),
+        })
+    }
+}
+
+impl LogReader {
+    /// Returns a builder for a reader of a table with a custom name or columns.
+    pub fn builder() -> LogReaderBuilder {
+        LogReaderBuilder::default()
+    }
+    /// Opens the `entry` table of the DB for reading.
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<LogReader> {
+        LogReader::builder().open(path)
+    }
+    /// Opens the given entry table of the DB for reading.
+    pub fn open_table<P: AsRef<Path>>(path: P, table: &str) -> anyhow::Result<LogReader> {
+        LogReader::builder().table(table).open(path)
+    }
+    /// Sets the text inserted before and after the matching terms in
+    /// [`SearchHit::snippet`], `[` and `]` by default.
+    pub fn set_snippet_markers(&mut self, start: &str, end: &str) {
+        self.snippet_markers = (start.to_string(), end.to_string());
+    }
+    /// Returns the highest rowid in the table, or 0 if it's empty. Entries
+    /// written later have higher rowids, so it can be used with
+    /// [`LogFilter::after_rowid`] to read only the new entries.
+    pub fn max_rowid(&self) -> anyhow::Result<i64> {
+        let rowid = self.conn.query_row(
+            &format!("select coalesce(max(rowid), 0) from {}", self.table),
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(rowid)
+    }
+    /// Reads the entries selected by the filter, oldest first unless
+    /// [`LogFilter::newest_first`] is set. The entries are read from the DB in
+    /// pages while iterating.
+    pub fn entries(&self, filter: &LogFilter) -> LogEntries<'_> {
+        LogEntries {
+            reader: self,
+            filter: filter.clone(),
+            page: VecDeque::new(),
+            last: None,
+            done: filter.limit == Some(0),
+        }
+    }
+    /// Finds the entries whose messages match the FTS5 query, e.g.
+    /// `connection AND (refused OR reset)`, best matches first. Requires the
+    /// appender to be created with `full_text_search` enabled.
+    pub fn search(&self, query: &str, limit: usize) -> anyhow::Result<Vec<SearchHit>> {
+        let table = &self.table;
+        if !schema::table_exists(&self.conn, &format!("{table}_fts"))? {
+            return Err(anyhow!(
+                "Table `{}` has no full-text index, enable `full_text_search` in the appender",
+                table
+            ));
+        }
+        let sql = format!(
+            "select {}, snippet({table}_fts, 0, ?2, ?3, '...', 16), f.rank
+            from {table}_fts f join {table} e on e.rowid = f.rowid
+            where {table}_fts match ?1
+            order by f.rank
+            limit ?4",
+            self.select_columns("e.")
+        );
+        let width = self.select_width();
+        let rows = self
+            .conn
+            .prepare(&sql)?
+            .query_map(
+                rusqlite::params![
+                    query,
+                    self.snippet_markers.0,
+                    self.snippet_markers.1,
+                    limit as i64
+                ],
+                |row| {
+                    let values = (0..width)
+                        .map(|i| row.get::<_, Value>(i))
+                        .collect::<rusqlite::Result<Vec<Value>>>()?;
+                    Ok((
+                        values,
+                        row.get::<_, String>(width)?,
+                        row.get::<_, f64>(width + 1)?,
+                    ))
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(|e| anyhow!("Error searching for {:?}: {}", query, e))?;
+        rows.into_iter()
+            .map(|(values, snippet, rank)| {
+                let (_, _, entry) = self.decode_entry(values)?;
+                Ok(SearchHit {
+                    entry,
+                    snippet,
+                    rank,
+                })
+            })
+            .collect()
+    }
+    // `rowid` first, then the fields in the order of `Field::ALL`, then the MDC columns.
+    fn select_columns(&self, alias: &str) -> String {
+        std::iter::once(format!("{alias}rowid"))
+            .chain(self.columns.iter().map(|(_, column)| match column {
+                Some(column) => format!("{alias}{column}"),
+                None => "null".to_string(),
+            }))
+            .chain(self.mdc_columns.iter().map(|c| format!("{alias}{c}")))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+    fn select_width(&self) -> usize {
+        1 + self.columns.len() + self.mdc_columns.len()
+    }
+    fn column(&self, field: Field) -> &str {
+        self.columns
+            .iter()
+            .find(|(f, _)| *f == field)
+            .and_then(|(_, c)| c.as_deref())
+            .unwrap_or("null")
+    }
+    // Returns the raw `ts` and the `rowid` together with the entry.
+    fn decode_entry(&self, values: Vec<Value>) -> anyhow::Result<(Value, i64, LogEntry)> {
+        let mut values = values.into_iter();
+        let rowid = match values.next() {
+            Some(Value::Integer(rowid)) => rowid,
+            value => return Err(anyhow!("Invalid rowid: {:?}", value)),
+        };
+        let mut fields = BTreeMap::new();
+        for (field, _) in self.columns.iter() {
+            fields.insert(*field, values.next().unwrap_or(Value::Null));
+        }
+        let mut take = |field: Field| fields.remove(&field).unwrap_or(Value::Null);
+        let raw_ts = take(Field::Ts);
+        let mut mdc = match decode_text(take(Field::Mdc)) {
+            Some(json) => serde_json::from_str::<BTreeMap<String, String>>(&json)
+                .map_err(|e| anyhow!("Invalid mdc {:?}: {}", json, e))?,
+            None => BTreeMap::new(),
+        };
+        for (column, value) in self.mdc_columns.iter().zip(values) {
+            if let Some(value) = decode_text(value) {
+                mdc.insert(column["mdc_".len()..].to_string(), value);
+            }
+        }
+        let entry = LogEntry {
+            rowid,
+            id: decode_id(take(Field::Id))?,
+            ts: decode_ts(raw_ts.clone())?,
+            level: decode_level(take(Field::Level))?,
+            message: decode_text(take(Field::Message)).unwrap_or_default(),
+            target: decode_text(take(Field::Target)),
+            module_path: decode_text(take(Field::ModulePath)),
+            file: decode_text(take(Field::File)),
+            line: decode_int("line", take(Field::Line))?,
+            thread_name: decode_text(take(Field::ThreadName)),
+            thread_id: decode_int("thread_id", take(Field::ThreadId))?,
+            process_id: decode_int("process_id", take(Field::ProcessId))?,
+            hostname: decode_text(take(Field::Hostname)),
+            mdc,
+            formatted: decode_text(take(Field::Formatted)),
+        };
+        Ok((raw_ts, rowid, entry))
+    }
+    fn read_page(
+        &self,
+        filter: &LogFilter,
+        last: Option<&(Value, i64)>,
+        limit: usize,
+    ) -> anyhow::Result<VecDeque<(Value, i64, LogEntry)>> {
+        let ts = self.column(Field::Ts);
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if let Some(since) = filter.since {
+            params.push(self.storage.ts_value(since));
+            conditions.push(format!("{ts} >= ?{}", params.len()));
+        }
+        if let Some(until) = filter.until {
+            params.push(self.storage.ts_value(until));
+            conditions.push(format!("{ts} < ?{}", params.len()));
+        }
+        if let Some(min_level) = filter.min_level {
+            let levels = log::Level::iter()
+                .filter(|l| *l <= min_level)
+                .map(|l| {
+                    params.push(self.storage.level_value(l));
+                    format!("?{}", params.len())
+                })
+                .collect::<Vec<String>>();
+            conditions.push(format!(
+                "{} in ({})",
+                self.column(Field::Level),
+                levels.join(", ")
+            ));
+        }
+        if let Some(prefix) = &filter.target_prefix {
+            params.push(Value::Text(prefix.clone()));
+            conditions.push(format!(
+                "substr({}, 1, length(?{n})) = ?{n}",
+                self.column(Field::Target),
+                n = params.len()
+            ));
+        }
+        if let Some(text) = &filter.message_contains {
+            params.push(Value::Text(text.clone()));
+            conditions.push(format!(
+                "instr({}, ?{}) > 0",
+                self.column(Field::Message),
+                params.len()
+            ));
+        }
+        if let Some(rowid) = filter.after_rowid {
+            params.push(Value::Integer(rowid));
+            conditions.push(format!("rowid > ?{}", params.len()));
+        }
+        let (cmp, order) = if filter.newest_first {
+            ("<", "desc")
+        } else {
+            (">", "asc")
+        };
+        let offset = match last {
+            Some((last_ts, last_rowid)) => {
+                params.push(last_ts.clone());
+                params.push(Value::Integer(*last_rowid));
+                conditions.push(format!(
+                    "({ts} {cmp} ?{a} or ({ts} = ?{a} and rowid {cmp} ?{b}))",
+                    a = params.len() - 1,
+                    b = params.len()
+                ));
+                0
+            }
+            None => filter.offset,
+        };
+        let filter_sql = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("where {}", conditions.join(" and "))
+        };
+        let sql = format!(
+            "select {} from {} {filter_sql} order by {ts} {order}, rowid {order} limit {limit} offset {offset}",
+            self.select_columns(""),
+            self.table
+        );
+        let width = self.select_width();
+        let rows = self
+            .conn
+            .prepare(&sql)?
+            .query_map(rusqlite::params_from_iter(params.iter()), |row| {
+                (0..width)
+                    .map(|i| row.get::<_, Value>(i))
+                    .collect::<rusqlite::Result<Vec<Value>>>()
+            })?
+            .collect::<rusqlite::Result<Vec<Vec<Value>>>>()?;
+        rows.into_iter().map(|v| self.decode_entry(v)).collect()
+    }
+}
+
+impl Iterator for LogEntries<'_> {
+    type Item = anyhow::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<anyhow::Result<LogEntry>> {
+        if self.page.is_empty() && !self.done {
+            let limit = self.filter.limit.map_or(PAGE_SIZE, |l| l.min(PAGE_SIZE));
+            match self
+                .reader
+                .read_page(&self.filter, self.last.as_ref(), limit)
+            {
+                Ok(page) => {
+                    self.done = page.len() < limit;
+                    self.page = page;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let (ts, rowid, entry) = self.page.pop_front()?;
+        self.last = Some((ts, rowid));
+        if let Some(limit) = self.filter.limit.as_mut() {
+            *limit -= 1;
+            self.done |= *limit == 0;
+        }
+        Some(Ok(entry))
+    }
+}
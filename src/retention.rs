@@ -0,0 +1,168 @@
+use anyhow::anyhow;
+use std::time::Duration;
+
+use crate::schema;
+use crate::Field;
+use crate::Options;
+
+// Limits the work done by one check, which may run on a logging thread. The
+// entries left over are deleted by the next checks.
+const MAX_BATCHES_PER_CHECK: u32 = 10;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Retention {
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) max_rows: Option<u64>,
+    pub(crate) max_db_size: Option<u64>,
+    pub(crate) check_interval: Duration,
+    pub(crate) batch_size: u64,
+    pub(crate) incremental_vacuum: bool,
+}
+
+impl Default for Retention {
+    fn default() -> Retention {
+        Retention {
+            max_age: None,
+            max_rows: None,
+            max_db_size: None,
+            check_interval: Duration::from_secs(60),
+            batch_size: 1000,
+            incremental_vacuum: false,
+        }
+    }
+}
+
+impl Retention {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_rows.is_some() || self.max_db_size.is_some()
+    }
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if self.max_age == Some(Duration::ZERO) {
+            return Err(anyhow!("Invalid `max_age`: must be greater than 0"));
+        }
+        if let Some(max_age) = self.max_age {
+            cutoff(max_age).map_err(|_| anyhow!("Invalid `max_age`: too long"))?;
+        }
+        if self.max_rows == Some(0) {
+            return Err(anyhow!("Invalid `max_rows`: must be greater than 0"));
+        }
+        if self.max_db_size == Some(0) {
+            return Err(anyhow!("Invalid `max_db_size`: must be greater than 0"));
+        }
+        if self.check_interval.is_zero() {
+            return Err(anyhow!(
+                "Invalid `retention_check_interval`: must be greater than 0"
+            ));
+        }
+        if self.batch_size == 0 {
+            return Err(anyhow!(
+                "Invalid `retention_batch_size`: must be greater than 0"
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Size of the pages in use, the pages on the free list are reused before the
+// file grows.
+fn used_size(conn: &rusqlite::Connection) -> anyhow::Result<u64> {
+    let page_count: u64 = conn.query_row("pragma page_count", [], |row| row.get(0))?;
+    let freelist_count: u64 = conn.query_row("pragma freelist_count", [], |row| row.get(0))?;
+    let page_size: u64 = conn.query_row("pragma page_size", [], |row| row.get(0))?;
+    Ok(page_count.saturating_sub(freelist_count) * page_size)
+}
+
+// Deletes up to `limit` oldest entries, only the ones older than `before` if
+// set, together with their key-values. Each batch is a separate transaction,
+// so that the DB is not locked for long.
+fn delete_batch(
+    conn: &mut rusqlite::Connection,
+    options: &Options,
+    kv: bool,
+    before: Option<&rusqlite::types::Value>,
+    limit: u64,
+) -> anyhow::Result<u64> {
+    let table = &options.table;
+    let id = options.column(Field::Id);
+    let ts = options.column(Field::Ts);
+    let filter = match before {
+        Some(_) => format!("where {ts} < ?1"),
+        None => String::new(),
+    };
+    let oldest = format!("select rowid from {table} {filter} order by {ts}, rowid limit {limit}");
+    let tx = conn.transaction()?;
+    if kv {
+        tx.execute(
+            &format!(
+                "delete from {table}_kv where entry_id in (select {id} from {table} where rowid in ({oldest}))"
+            ),
+            rusqlite::params_from_iter(before.iter()),
+        )?;
+    }
+    let deleted = tx.execute(
+        &format!("delete from {table} where rowid in ({oldest})"),
+        rusqlite::params_from_iter(before.iter()),
+    )?;
+    tx.commit()?;
+    Ok(deleted as u64)
+}
+
+// Time before which the entries are older than `max_age`.
+fn cutoff(max_age: Duration) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    chrono::Duration::from_std(max_age)
+        .ok()
+        .and_then(|max_age| chrono::Utc::now().checked_sub_signed(max_age))
+        .ok_or_else(|| anyhow!("`max_age` of {:?} is out of range", max_age))
+}
+
+pub(crate) fn enforce(conn: &mut rusqlite::Connection, options: &Options) -> anyhow::Result<u64> {
+    let retention = &options.retention;
+    let table = &options.table;
+    let batch_size = retention.batch_size;
+    let kv = schema::table_exists(conn, &format!("{table}_kv"))?;
+    let mut deleted = 0;
+    let mut batches = 0;
+    if let Some(max_age) = retention.max_age {
+        let cutoff = options.storage.ts_value(cutoff(max_age)?);
+        while batches < MAX_BATCHES_PER_CHECK {
+            let n = delete_batch(conn, options, kv, Some(&cutoff), batch_size)?;
+            batches += 1;
+            deleted += n;
+            if n < batch_size {
+                break;
+            }
+        }
+    }
+    if let Some(max_rows) = retention.max_rows {
+        let count: u64 = conn.query_row(&format!("select count(*) from {table}"), [], |row| {
+            row.get(0)
+        })?;
+        let mut excess = count.saturating_sub(max_rows);
+        while excess > 0 && batches < MAX_BATCHES_PER_CHECK {
+            let n = delete_batch(conn, options, kv, None, excess.min(batch_size))?;
+            batches += 1;
+            if n == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(n);
+            deleted += n;
+        }
+    }
+    if let Some(max_db_size) = retention.max_db_size {
+        while batches < MAX_BATCHES_PER_CHECK && used_size(conn)? > max_db_size {
+            let n = delete_batch(conn, options, kv, None, batch_size)?;
+            batches += 1;
+            if n == 0 {
+                break;
+            }
+            deleted += n;
+        }
+    }
+    if deleted > 0 && retention.incremental_vacuum {
+        // The pages are freed while stepping through the statement.
+        let mut stmt = conn.prepare("pragma incremental_vacuum")?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+    }
+    Ok(deleted)
+}
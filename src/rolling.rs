@@ -0,0 +1,133 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Timelike};
+use log4rs::append::rolling_file::policy::compound::roll::Roll;
+use log4rs::append::rolling_file::policy::compound::trigger::time::TimeTriggerInterval;
+use std::path::Path;
+use std::sync::Arc;
+
+/// When the DB file is rolled over, see [`SqliteLogAppenderBuilder::rolling`](crate::SqliteLogAppenderBuilder::rolling).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RollTrigger {
+    /// When the DB file, together with its WAL file, is larger than that many bytes.
+    Size(u64),
+    /// When the local time passes the next interval boundary, computed in the
+    /// same way as by the log4rs time trigger.
+    Time {
+        interval: TimeTriggerInterval,
+        modulate: bool,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Rolling {
+    pub(crate) trigger: RollTrigger,
+    pub(crate) roller: Arc<dyn Roll>,
+}
+
+impl Rolling {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        match self.trigger {
+            RollTrigger::Size(0) => Err(anyhow!(
+                "Invalid `policy`: size trigger limit must be greater than 0"
+            )),
+            RollTrigger::Time { interval, .. } if interval_count(interval) <= 0 => Err(anyhow!(
+                "Invalid `policy`: time trigger interval must be greater than 0"
+            )),
+            _ => Ok(()),
+        }
+    }
+    pub(crate) fn next_roll_time(&self) -> Option<DateTime<Local>> {
+        match self.trigger {
+            RollTrigger::Size(_) => None,
+            RollTrigger::Time { interval, modulate } => {
+                Some(next_time(interval, modulate, Local::now()))
+            }
+        }
+    }
+    pub(crate) fn is_due(&self, path: &Path, next_roll_time: Option<DateTime<Local>>) -> bool {
+        match self.trigger {
+            RollTrigger::Size(limit) => db_size(path) > limit,
+            RollTrigger::Time { .. } => next_roll_time.is_some_and(|t| Local::now() >= t),
+        }
+    }
+}
+
+fn interval_count(interval: TimeTriggerInterval) -> i64 {
+    match interval {
+        TimeTriggerInterval::Second(n)
+        | TimeTriggerInterval::Minute(n)
+        | TimeTriggerInterval::Hour(n)
+        | TimeTriggerInterval::Day(n)
+        | TimeTriggerInterval::Week(n)
+        | TimeTriggerInterval::Month(n)
+        | TimeTriggerInterval::Year(n) => n,
+    }
+}
+
+fn db_size(path: &Path) -> u64 {
+    let mut wal_path = path.as_os_str().to_owned();
+    wal_path.push("-wal");
+    [path, Path::new(&wal_path)]
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn local(date: NaiveDate, hour: u32, min: u32, sec: u32) -> DateTime<Local> {
+    let naive = date.and_hms_opt(hour, min, sec).unwrap_or_default();
+    // Local times skipped by a DST change don't exist, take the UTC one then.
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .unwrap_or_else(|| Local.from_utc_datetime(&naive))
+}
+
+// Start of the next interval, with `modulate` aligned to multiples of the
+// interval, e.g. 00:00, 06:00, 12:00 and 18:00 for 6 hours.
+fn next_time(
+    interval: TimeTriggerInterval,
+    modulate: bool,
+    current: DateTime<Local>,
+) -> DateTime<Local> {
+    let increment = |n: i64, value: i64| if modulate { n - value % n } else { n };
+    let date = current.date_naive();
+    match interval {
+        TimeTriggerInterval::Year(n) => {
+            let year = current.year() + increment(n, current.year() as i64) as i32;
+            local(NaiveDate::from_ymd_opt(year, 1, 1).unwrap_or(date), 0, 0, 0)
+        }
+        TimeTriggerInterval::Month(n) => {
+            let month0 = current.month0() as i64;
+            let months = current.year() as i64 * 12 + month0 + increment(n, month0);
+            local(
+                NaiveDate::from_ymd_opt((months / 12) as i32, (months % 12) as u32 + 1, 1)
+                    .unwrap_or(date),
+                0,
+                0,
+                0,
+            )
+        }
+        TimeTriggerInterval::Week(n) => {
+            let week0 = current.iso_week().week0() as i64;
+            let weekday = current.weekday().num_days_from_monday() as i64;
+            local(date, 0, 0, 0) + Duration::weeks(increment(n, week0)) - Duration::days(weekday)
+        }
+        TimeTriggerInterval::Day(n) => {
+            local(date, 0, 0, 0) + Duration::days(increment(n, current.ordinal0() as i64))
+        }
+        TimeTriggerInterval::Hour(n) => {
+            let hour = current.hour();
+            local(date, hour, 0, 0) + Duration::hours(increment(n, hour as i64))
+        }
+        TimeTriggerInterval::Minute(n) => {
+            let min = current.minute();
+            local(date, current.hour(), min, 0) + Duration::minutes(increment(n, min as i64))
+        }
+        TimeTriggerInterval::Second(n) => {
+            let sec = current.second();
+            local(date, current.hour(), current.minute(), sec)
+                + Duration::seconds(increment(n, sec as i64))
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::anyhow;
+
+use crate::Field;
+use crate::IdStrategy;
+use crate::Options;
+use crate::Storage;
+
+const VERSION_TABLE: &str = "x_log4rs_sqlite_schema";
+
+type Migration = fn(&rusqlite::Transaction, &Options) -> anyhow::Result<()>;
+
+// Migration at index `i` upgrades a table from version `i` to version `i + 1`.
+const MIGRATIONS: &[Migration] = &[create_entry_table, add_record_details];
+
+const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+const RECORD_DETAIL_COLUMNS: &[(Field, &str)] = &[
+    (Field::Target, "varchar(256)"),
+    (Field::ModulePath, "varchar(256)"),
+    (Field::File, "varchar(1024)"),
+    (Field::Line, "integer"),
+    (Field::ThreadName, "varchar(256)"),
+    (Field::ThreadId, "integer"),
+    (Field::ProcessId, "integer"),
+    (Field::Hostname, "varchar(256)"),
+    (Field::Mdc, "text"),
+    (Field::Formatted, "text"),
+];
+
+fn create_entry_table(tx: &rusqlite::Transaction, options: &Options) -> anyhow::Result<()> {
+    let table = &options.table;
+    let (ts_decl, level_decl) = match options.storage {
+        Storage::Text => ("varchar(128)", "varchar(128)"),
+        Storage::Compact => ("integer", "integer"),
+    };
+    let id_decl = match options.id_strategy {
+        IdStrategy::Rowid => "integer primary key autoincrement",
+        _ => "varchar(128) not null primary key",
+    };
+    let table_sql = format!(
+        "create table if not exists {table} (
+            {id} {id_decl},
+            {ts} {ts_decl} not null,
+            {level} {level_decl} not null,
+            {message} varchar(8192) not null
+        )",
+        id = options.column(Field::Id),
+        ts = options.column(Field::Ts),
+        level = options.column(Field::Level),
+        message = options.column(Field::Message),
+    );
+    let index_ts_sql = format!(
+        "create index if not exists {table}_ts_i on {table} ({})",
+        options.column(Field::Ts)
+    );
+    tx.execute(&table_sql, [])?;
+    tx.execute(&index_ts_sql, [])?;
+    Ok(())
+}
+
+// Rebuilds a table written by 0.0.1 with the layout of 0.0.2, giving the
+// existing entries random ids, as 0.0.2 did. Both layouts are those of the
+// DBs written by the released crates, see `tests/fixtures`.
+fn add_entry_ids(tx: &rusqlite::Transaction, table: &str) -> anyhow::Result<()> {
+    let old = format!("{table}_0_0_1");
+    tx.execute_batch(&format!(
+        "alter table {table} rename to {old};
+        drop index if exists {table}_ts_i;
+        create table {table} (
+            id varchar(128) not null primary key,
+            ts varchar(128) not null,
+            level varchar(128) not null,
+            message varchar(8192) not null
+        );
+        create index {table}_ts_i on {table} (ts);"
+    ))?;
+    {
+        let mut select = tx.prepare(&format!(
+            "select ts, level, message from {old} order by rowid"
+        ))?;
+        let mut insert = tx.prepare(&format!(
+            "insert into {table} (id, ts, level, message) values (?1, ?2, ?3, ?4)"
+        ))?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            insert.execute(rusqlite::params![
+                uuid::Uuid::new_v4().to_string(),
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ])?;
+        }
+    }
+    tx.execute(&format!("drop table {old}"), [])?;
+    Ok(())
+}
+
+fn add_record_details(tx: &rusqlite::Transaction, options: &Options) -> anyhow::Result<()> {
+    add_missing_columns(tx, &options.table, &record_detail_columns(options))
+}
+
+pub(crate) fn record_detail_columns(options: &Options) -> Vec<(String, &'static str)> {
+    RECORD_DETAIL_COLUMNS
+        .iter()
+        .map(|(field, decl)| (options.column(*field).to_string(), *decl))
+        .collect()
+}
+
+pub(crate) fn table_exists(conn: &rusqlite::Connection, table: &str) -> anyhow::Result<bool> {
+    let count: i64 = conn.query_row(
+        "select count(*) from sqlite_master where type = 'table' and name = ?1",
+        [table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+pub(crate) fn table_columns(
+    conn: &rusqlite::Connection,
+    table: &str,
+) -> anyhow::Result<Vec<String>> {
+    let columns = conn
+        .prepare(&format!("pragma table_info({table})"))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(columns)
+}
+
+pub(crate) fn column_type(
+    conn: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+) -> anyhow::Result<Option<String>> {
+    let columns = conn
+        .prepare(&format!("pragma table_info({table})"))?
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+    Ok(columns
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(column))
+        .map(|(_, decl)| decl))
+}
+
+// Recreated on every open, so that it includes the columns added since.
+pub(crate) fn create_compact_view(
+    conn: &rusqlite::Connection,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let table = &options.table;
+    let ts = options.column(Field::Ts);
+    let level = options.column(Field::Level);
+    let columns = table_columns(conn, table)?
+        .into_iter()
+        .map(|c| {
+            if c.eq_ignore_ascii_case(ts) {
+                format!(
+                    "strftime('%Y-%m-%d %H:%M:%S', {ts} / 1000000, 'unixepoch') || printf('.%06d', {ts} % 1000000) as {ts}"
+                )
+            } else if c.eq_ignore_ascii_case(level) {
+                format!(
+                    "case {level} when 1 then 'ERROR' when 2 then 'WARN' when 3 then 'INFO' when 4 then 'DEBUG' when 5 then 'TRACE' end as {level}"
+                )
+            } else {
+                c
+            }
+        })
+        .collect::<Vec<String>>();
+    conn.execute_batch(&format!(
+        "drop view if exists {table}_view;
+        create view {table}_view as select {} from {table};",
+        columns.join(", ")
+    ))?;
+    Ok(())
+}
+
+// External content FTS5 table, kept in sync with the entry table by triggers,
+// so that the entries deleted by retention are removed from the index too.
+pub(crate) fn create_full_text_index(
+    conn: &mut rusqlite::Connection,
+    options: &Options,
+) -> anyhow::Result<()> {
+    let table = &options.table;
+    let message = options.column(Field::Message);
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    if table_exists(&tx, &format!("{table}_fts"))? {
+        return Ok(());
+    }
+    tx.execute_batch(&format!(
+        "create virtual table {table}_fts using fts5({message}, content='{table}', content_rowid='rowid');
+        create trigger {table}_fts_ai after insert on {table} begin
+            insert into {table}_fts (rowid, {message}) values (new.rowid, new.{message});
+        end;
+        create trigger {table}_fts_ad after delete on {table} begin
+            insert into {table}_fts ({table}_fts, rowid, {message}) values ('delete', old.rowid, old.{message});
+        end;
+        create trigger {table}_fts_au after update of {message} on {table} begin
+            insert into {table}_fts ({table}_fts, rowid, {message}) values ('delete', old.rowid, old.{message});
+            insert into {table}_fts (rowid, {message}) values (new.rowid, new.{message});
+        end;
+        insert into {table}_fts ({table}_fts) values ('rebuild');"
+    ))?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub(crate) fn add_missing_columns(
+    conn: &rusqlite::Connection,
+    table: &str,
+    columns: &[(String, &str)],
+) -> anyhow::Result<()> {
+    let existing = table_columns(conn, table)?;
+    for (name, decl) in columns {
+        if !existing.iter().any(|c| c.eq_ignore_ascii_case(name)) {
+            conn.execute(&format!("alter table {table} add column {name} {decl}"), [])?;
+        }
+    }
+    Ok(())
+}
+
+fn read_version(conn: &rusqlite::Connection, table: &str) -> anyhow::Result<Option<i64>> {
+    if !table_exists(conn, VERSION_TABLE)? {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(&format!(
+        "select version from {VERSION_TABLE} where table_name = ?1"
+    ))?;
+    let mut rows = stmt.query([table])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn migrate(conn: &mut rusqlite::Connection, options: &Options) -> anyhow::Result<()> {
+    let table = &options.table;
+    let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    // Tables created before the schema was versioned have the layout of version 1,
+    // once the ids missing in 0.0.1 are added.
+    let version = match read_version(&tx, table)? {
+        Some(version) => version,
+        None if table_exists(&tx, table)? => {
+            if table_columns(&tx, table)? == ["ts", "level", "message"] {
+                add_entry_ids(&tx, table)?;
+            }
+            1
+        }
+        None => 0,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "Table `{}` has schema version {}, but this version of x-log4rs-sqlite supports only versions up to {}",
+            table,
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&tx, options)?;
+    }
+    tx.execute(
+        &format!(
+            "create table if not exists {VERSION_TABLE} (
+                table_name varchar(128) not null primary key,
+                version integer not null
+            )"
+        ),
+        [],
+    )?;
+    tx.execute(
+        &format!("insert or replace into {VERSION_TABLE} (table_name, version) values (?1, ?2)"),
+        rusqlite::params![table, SCHEMA_VERSION],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counters of the appender, see [`SqliteLogAppender::stats`](crate::SqliteLogAppender::stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Entries written to the DB.
+    pub written: u64,
+    /// Batches retried after a transient error, counted for each retry.
+    pub retries: u64,
+    /// Batches that couldn't be written after all the retries, or because of
+    /// an error that isn't transient, counted once per batch.
+    pub failed_batches: u64,
+    /// Entries dropped because the buffer was full.
+    pub dropped: u64,
+    /// Entries forwarded to the fallback appender.
+    pub forwarded: u64,
+}
+
+/// Handle to the counters of an appender, usable after the appender is moved
+/// into the log4rs config, see [`SqliteLogAppender::stats_handle`](crate::SqliteLogAppender::stats_handle).
+#[derive(Clone, Debug)]
+pub struct StatsHandle(pub(crate) Arc<Counters>);
+
+impl StatsHandle {
+    /// Returns the current values of the counters.
+    pub fn get(&self) -> Stats {
+        self.0.get()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) written: AtomicU64,
+    pub(crate) retries: AtomicU64,
+    pub(crate) failed_batches: AtomicU64,
+    pub(crate) dropped: AtomicU64,
+    pub(crate) forwarded: AtomicU64,
+    // Entries dropped since the last successful write, which are recorded in
+    // the DB with the next one.
+    pub(crate) unreported_dropped: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+    pub(crate) fn count_dropped(&self) {
+        Counters::add(&self.dropped, 1);
+        Counters::add(&self.unreported_dropped, 1);
+    }
+    pub(crate) fn take_unreported_dropped(&self) -> u64 {
+        self.unreported_dropped.swap(0, Ordering::Relaxed)
+    }
+    pub(crate) fn get(&self) -> Stats {
+        Stats {
+            written: self.written.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            failed_batches: self.failed_batches.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            forwarded: self.forwarded.load(Ordering::Relaxed),
+        }
+    }
+}
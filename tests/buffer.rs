@@ -0,0 +1,164 @@
+mod common;
+
+use log4rs::append::Append;
+use std::path::Path;
+use std::time::Duration;
+use x_log4rs_sqlite::OverflowPolicy;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+fn entries(path: &Path) -> Vec<(String, String, String)> {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    let mut stmt = conn
+        .prepare("select level, target, message from entry order by ts, rowid")
+        .unwrap();
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap()
+}
+
+fn entry(level: &str, target: &str, message: &str) -> (String, String, String) {
+    (level.to_string(), target.to_string(), message.to_string())
+}
+
+#[test]
+fn drops_oldest_entries_while_db_is_locked() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(1)
+        .max_buffer_size(3)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(0)
+        .build()
+        .unwrap();
+    let conn = common::lock(&path);
+    for i in 0..5 {
+        // Errors are expected while the DB is locked.
+        let _ = common::try_append(&appender, log::Level::Info, &i.to_string());
+    }
+    drop(conn);
+    appender.flush();
+    assert_eq!(
+        entries(&path),
+        vec![
+            entry("INFO", common::TARGET, "2"),
+            entry("INFO", common::TARGET, "3"),
+            entry("INFO", common::TARGET, "4"),
+            entry(
+                "WARN",
+                "x_log4rs_sqlite",
+                "2 log entries dropped because the buffer was full"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn drops_entries_below_level_first() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(1)
+        .max_buffer_size(3)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(0)
+        .overflow_policy(OverflowPolicy::DropBelow(log::Level::Warn))
+        .build()
+        .unwrap();
+    let conn = common::lock(&path);
+    // Errors are expected while the DB is locked.
+    let _ = common::try_append(&appender, log::Level::Error, "a");
+    let _ = common::try_append(&appender, log::Level::Info, "b");
+    let _ = common::try_append(&appender, log::Level::Debug, "c");
+    let _ = common::try_append(&appender, log::Level::Info, "d");
+    let _ = common::try_append(&appender, log::Level::Warn, "e");
+    drop(conn);
+    appender.flush();
+    assert_eq!(
+        entries(&path),
+        vec![
+            entry("ERROR", common::TARGET, "a"),
+            entry("DEBUG", common::TARGET, "c"),
+            entry("WARN", common::TARGET, "e"),
+            entry(
+                "WARN",
+                "x_log4rs_sqlite",
+                "2 log entries dropped because the buffer was full"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn does_not_block_callers_in_background_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .background(true)
+        .buffer_size(1)
+        .max_buffer_size(2)
+        .busy_timeout(Duration::from_secs(1))
+        .max_retries(0)
+        .overflow_policy(OverflowPolicy::DropNewest)
+        .on_error(|_| {})
+        .build()
+        .unwrap();
+    let stats = appender.stats_handle();
+    let conn = common::lock(&path);
+    let start = std::time::Instant::now();
+    for i in 0..10 {
+        common::append(&appender, &i.to_string());
+    }
+    assert!(
+        start.elapsed() < Duration::from_millis(500),
+        "appends took {:?}",
+        start.elapsed()
+    );
+    drop(conn);
+    appender.flush();
+    let dropped = stats.get().dropped;
+    assert!(dropped > 0);
+    let entries = entries(&path);
+    assert_eq!(entries.len() as u64, 10 - dropped + 1);
+    assert_eq!(entries[0], entry("INFO", common::TARGET, "0"));
+    assert_eq!(
+        entries.last().unwrap(),
+        &entry(
+            "WARN",
+            "x_log4rs_sqlite",
+            &format!(
+                "{} log entries dropped because the buffer was full",
+                dropped
+            )
+        )
+    );
+}
+
+#[test]
+fn drops_less_severe_queued_entries_in_background_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .background(true)
+        .buffer_size(1)
+        .max_buffer_size(2)
+        .busy_timeout(Duration::from_secs(1))
+        .max_retries(0)
+        .overflow_policy(OverflowPolicy::DropBelow(log::Level::Warn))
+        .on_error(|_| {})
+        .build()
+        .unwrap();
+    let conn = common::lock(&path);
+    for i in 0..10 {
+        common::append_level(&appender, log::Level::Debug, &i.to_string());
+    }
+    common::append_level(&appender, log::Level::Error, "disk full");
+    drop(conn);
+    appender.flush();
+    assert!(entries(&path).contains(&entry("ERROR", common::TARGET, "disk full")));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use log4rs::append::Append;
+use x_log4rs_sqlite::LogEntry;
+use x_log4rs_sqlite::LogFilter;
+use x_log4rs_sqlite::LogReader;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+fn log_entry(capture: bool) -> (LogEntry, u64) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .capture_thread_name(capture)
+        .capture_thread_id(capture)
+        .capture_process_id(capture)
+        .capture_hostname(capture)
+        .build()
+        .unwrap();
+    let thread_id = std::thread::scope(|s| {
+        std::thread::Builder::new()
+            .name("capture-test".to_string())
+            .spawn_scoped(s, || {
+                common::append(&appender, "captured");
+                thread_id::get() as u64
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    });
+    appender.flush();
+    let reader = LogReader::open(&path).unwrap();
+    let entry = reader.entries(&LogFilter::new()).next().unwrap().unwrap();
+    (entry, thread_id)
+}
+
+#[test]
+fn captures_thread_process_and_host() {
+    let (entry, thread_id) = log_entry(true);
+    assert_eq!(entry.thread_name.as_deref(), Some("capture-test"));
+    assert_eq!(entry.thread_id, Some(thread_id));
+    assert_eq!(entry.process_id, Some(std::process::id()));
+    assert_eq!(
+        entry.hostname,
+        Some(hostname::get().unwrap().to_string_lossy().into_owned())
+    );
+    let (entry, _) = log_entry(false);
+    assert_eq!(entry.thread_name, None);
+    assert_eq!(entry.thread_id, None);
+    assert_eq!(entry.process_id, None);
+    assert_eq!(entry.hostname, None);
+}
@@ -0,0 +1,51 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use log4rs::append::Append;
+use std::path::Path;
+
+/// Target of the entries appended by [`try_append`], [`append`] and [`append_level`].
+pub const TARGET: &str = "test";
+
+/// Line number recorded for every appended entry.
+pub const LINE: u32 = 7;
+
+/// Appends an entry and returns the appender's result.
+pub fn record(
+    appender: &dyn Append,
+    level: log::Level,
+    target: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    appender.append(
+        &log::Record::builder()
+            .level(level)
+            .target(target)
+            .args(format_args!("{}", message))
+            .line(Some(LINE))
+            .build(),
+    )
+}
+
+/// Appends an entry with the default target and returns the appender's result.
+pub fn try_append(appender: &dyn Append, level: log::Level, message: &str) -> anyhow::Result<()> {
+    record(appender, level, TARGET, message)
+}
+
+/// Appends an info entry, panicking if the append fails.
+pub fn append(appender: &dyn Append, message: &str) {
+    append_level(appender, log::Level::Info, message);
+}
+
+/// Appends an entry with the given level, panicking if the append fails.
+pub fn append_level(appender: &dyn Append, level: log::Level, message: &str) {
+    try_append(appender, level, message).unwrap();
+}
+
+/// Opens a connection holding an exclusive lock on the DB.
+pub fn lock(path: &Path) -> rusqlite::Connection {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.execute_batch("begin exclusive").unwrap();
+    conn
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use log4rs::append::Append;
+use log4rs::config::Deserialize;
+use log4rs::config::Deserializers;
+use x_log4rs_sqlite::LogFilter;
+use x_log4rs_sqlite::LogReader;
+use x_log4rs_sqlite::SqliteLogAppenderConfig;
+use x_log4rs_sqlite::SqliteLogAppenderDeserializer;
+
+fn deserialize(yaml: &str) -> anyhow::Result<Box<dyn Append>> {
+    let config: SqliteLogAppenderConfig = serde_yaml::from_str(yaml)?;
+    SqliteLogAppenderDeserializer {}.deserialize(config, &Deserializers::default())
+}
+
+fn error(yaml: &str) -> String {
+    match deserialize(yaml) {
+        Ok(_) => panic!("config should be rejected:\n{}", yaml),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn builds_appender_from_yaml() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = deserialize(&format!(
+        "path: {:?}\n\
+         table: app_log\n\
+         buffer_size: 10\n\
+         flush_interval: 1s\n\
+         max_db_size: 10MB\n\
+         overflow_policy:\n  drop_below: warn\n",
+        path
+    ))
+    .unwrap();
+    common::append(appender.as_ref(), "hello");
+    appender.flush();
+    let reader = LogReader::open_table(&path, "app_log").unwrap();
+    let entry = reader.entries(&LogFilter::new()).next().unwrap().unwrap();
+    assert_eq!(entry.message, "hello");
+}
+
+#[test]
+fn names_invalid_field_in_error() {
+    for (yaml, expected) in [
+        ("path: ''", "Invalid `path`"),
+        ("path: log.sqlite\nbuffer_size: 0", "Invalid `buffer_size`"),
+        (
+            "path: log.sqlite\nbuffer_size: many",
+            "Invalid `buffer_size`",
+        ),
+        (
+            "path: log.sqlite\nflush_interval: soon",
+            "Invalid `flush_interval`",
+        ),
+        (
+            "path: log.sqlite\nmax_db_size: 10 parsecs",
+            "Invalid `max_db_size`",
+        ),
+        (
+            "path: log.sqlite\nmax_age: 1000000years",
+            "Invalid `max_age`",
+        ),
+        ("path: log.sqlite\ntable: 'app log'", "Invalid `table`"),
+        (
+            "path: log.sqlite\nencoder:\n  kind: xml",
+            "Invalid `encoder`",
+        ),
+        (
+            "path: log.sqlite\nencoder:\n  kind: pattern\n  patern: '{m}'",
+            "Invalid `encoder`",
+        ),
+        (
+            "path: log.sqlite\noverflow_policy: wait",
+            "Invalid `overflow_policy`",
+        ),
+    ] {
+        let error = error(yaml);
+        assert!(error.starts_with(expected), "{:?}: {}", yaml, error);
+    }
+}
+
+#[test]
+fn rejects_unknown_fields() {
+    let error = serde_yaml::from_str::<SqliteLogAppenderConfig>("path: log.sqlite\nbufer_size: 10")
+        .unwrap_err();
+    assert!(error.to_string().contains("bufer_size"), "{}", error);
+}
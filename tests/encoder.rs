@@ -0,0 +1,42 @@
+mod common;
+
+use log4rs::append::Append;
+use log4rs::encode::pattern::PatternEncoder;
+use x_log4rs_sqlite::LogFilter;
+use x_log4rs_sqlite::LogReader;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+#[test]
+fn saves_encoded_entry_in_formatted_column() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .encoder(Box::new(PatternEncoder::new(
+            "{l} {t}:{L} {X(request_id)} - {m}{n}",
+        )))
+        .build()
+        .unwrap();
+    log_mdc::insert("request_id", "r1");
+    common::append_level(&appender, log::Level::Warn, "disk full");
+    log_mdc::clear();
+    appender.flush();
+    let reader = LogReader::open(&path).unwrap();
+    let entry = reader.entries(&LogFilter::new()).next().unwrap().unwrap();
+    assert_eq!(
+        entry.formatted.as_deref(),
+        Some(format!("WARN {}:{} r1 - disk full\n", common::TARGET, common::LINE).as_str())
+    );
+}
+
+#[test]
+fn leaves_formatted_column_empty_without_encoder() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder().path(&path).build().unwrap();
+    common::append(&appender, "plain");
+    appender.flush();
+    let reader = LogReader::open(&path).unwrap();
+    let entry = reader.entries(&LogFilter::new()).next().unwrap().unwrap();
+    assert_eq!(entry.formatted, None);
+}
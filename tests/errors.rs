@@ -0,0 +1,52 @@
+mod common;
+
+use log4rs::append::Append;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+fn builder(path: &std::path::Path) -> x_log4rs_sqlite::SqliteLogAppenderBuilder {
+    SqliteLogAppender::builder()
+        .path(path)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(0)
+}
+
+#[test]
+fn try_flush_returns_error_and_flush_does_not_panic() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    for background in [false, true] {
+        let appender = builder(&path).background(background).build().unwrap();
+        common::append(&appender, "one");
+        let conn = common::lock(&path);
+        let e = appender.try_flush().unwrap_err();
+        assert!(format!("{:#}", e).contains("locked"), "{:#}", e);
+        appender.flush();
+        conn.execute_batch("commit").unwrap();
+        appender.try_flush().unwrap();
+    }
+}
+
+#[test]
+fn reports_errors_to_callback() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let appender = builder(&path)
+        .buffer_size(1)
+        .on_error({
+            let errors = errors.clone();
+            move |e| errors.lock().unwrap().push(format!("{:#}", e))
+        })
+        .build()
+        .unwrap();
+    let conn = common::lock(&path);
+    common::append(&appender, "one");
+    appender.flush();
+    conn.execute_batch("commit").unwrap();
+    appender.flush();
+    let errors = errors.lock().unwrap();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors.iter().all(|e| e.contains("locked")), "{:?}", errors);
+}
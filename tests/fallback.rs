@@ -0,0 +1,52 @@
+mod common;
+
+use log4rs::append::file::FileAppender;
+use log4rs::append::Append;
+use log4rs::encode::pattern::PatternEncoder;
+use std::time::Duration;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+#[test]
+fn forwards_entries_to_fallback_while_db_is_locked() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let fallback_path = dir.path().join("fallback.log");
+    let fallback = FileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(
+            "{l} {t} {X(request_id)} - {m}{n}",
+        )))
+        .build(&fallback_path)
+        .unwrap();
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(2)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(0)
+        .mdc_keys(vec!["request_id".to_string()])
+        .fallback(Box::new(fallback))
+        .build()
+        .unwrap();
+    let conn = common::lock(&path);
+    log_mdc::insert("request_id", "r1");
+    // Errors are expected while the DB is locked.
+    let _ = common::try_append(&appender, log::Level::Info, "one");
+    log_mdc::insert("request_id", "r2");
+    let _ = common::try_append(&appender, log::Level::Info, "two");
+    let _ = common::try_append(&appender, log::Level::Info, "three");
+    appender.flush();
+    conn.execute_batch("commit").unwrap();
+    common::append(&appender, "four");
+    appender.flush();
+    assert_eq!(
+        std::fs::read_to_string(&fallback_path).unwrap(),
+        "INFO test r1 - one\nINFO test r2 - two\nINFO test r2 - three\n"
+    );
+    let count: u64 = conn
+        .query_row(
+            "select count(*) from entry where message = 'four'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(count, 1);
+}
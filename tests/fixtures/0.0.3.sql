@@ -0,0 +1,12 @@
+-- Database written by x-log4rs-sqlite 0.0.3.
+create table if not exists entry (
+    id varchar(128) not null primary key,
+    ts varchar(128) not null,
+    level varchar(128) not null,
+    message varchar(8192) not null
+);
+create index if not exists entry_ts_i on entry (ts);
+insert into entry (id, ts, level, message) values
+    ('41f3456a-ea9e-488c-a6d8-7b171ee909a3', '2023-09-26 18:47:26.413370', 'INFO', 'test log message 999995'),
+    ('151240f6-75c2-4163-85ba-ff316bffc028', '2023-09-26 18:47:26.413375', 'INFO', 'test log message 999996'),
+    ('a906fc6c-44b1-4147-a268-8ea88aac119b', '2023-09-26 18:47:26.413381', 'WARN', 'test log message 999997');
@@ -0,0 +1,37 @@
+mod common;
+
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+fn count(path: &Path) -> i64 {
+    rusqlite::Connection::open(path)
+        .unwrap()
+        .query_row("select count(*) from entry", [], |row| row.get(0))
+        .unwrap()
+}
+
+#[test]
+fn writes_out_buffer_after_flush_interval() {
+    for background in [false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sqlite");
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .background(background)
+            .buffer_size(100)
+            .flush_interval(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        common::append(&appender, "one");
+        common::append(&appender, "two");
+        assert_eq!(count(&path), 0, "background: {}", background);
+        // Written by the timer or the writer thread, without further entries.
+        let start = Instant::now();
+        while count(&path) < 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(count(&path), 2, "background: {}", background);
+    }
+}
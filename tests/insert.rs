@@ -0,0 +1,80 @@
+use log4rs::append::Append;
+use std::path::Path;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+// More than fit in one multi-row insert statement.
+const COUNT: usize = 5000;
+
+fn append(appender: &SqliteLogAppender, i: usize) {
+    log_mdc::insert("request_id", format!("r{}", i));
+    let mut builder = log::Record::builder();
+    builder.level(log::Level::Info).target("insert_test");
+    #[cfg(feature = "kv")]
+    let kv = [("i", i as i64)];
+    #[cfg(feature = "kv")]
+    builder.key_values(&kv);
+    appender
+        .append(&builder.args(format_args!("{}", i)).build())
+        .unwrap();
+}
+
+fn rows(path: &Path) -> Vec<(String, String)> {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    let mut stmt = conn
+        .prepare("select message, mdc_request_id from entry order by rowid")
+        .unwrap();
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap()
+}
+
+#[test]
+fn writes_same_rows_with_multi_and_single_row_inserts() {
+    let dir = tempfile::tempdir().unwrap();
+    for multi_row_insert in [true, false] {
+        let path = dir.path().join(format!("{}.sqlite", multi_row_insert));
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .buffer_size(COUNT)
+            .mdc_keys(vec!["request_id".to_string()])
+            .multi_row_insert(multi_row_insert)
+            .build()
+            .unwrap();
+        for i in 0..COUNT {
+            append(&appender, i);
+        }
+        appender.flush();
+        let expected = (0..COUNT)
+            .map(|i| (i.to_string(), format!("r{}", i)))
+            .collect::<Vec<_>>();
+        assert_eq!(rows(&path), expected);
+    }
+}
+
+#[cfg(feature = "kv")]
+#[test]
+fn links_key_values_to_rowid_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(COUNT)
+        .id_strategy(x_log4rs_sqlite::IdStrategy::Rowid)
+        .build()
+        .unwrap();
+    for i in 0..COUNT {
+        append(&appender, i);
+    }
+    appender.flush();
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let linked: u64 = conn
+        .query_row(
+            "select count(*) from entry e join entry_kv k on k.entry_id = e.id
+            where k.key = 'i' and k.value = cast(e.message as integer)",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(linked, COUNT as u64);
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use log4rs::append::Append;
+use x_log4rs_sqlite::Field;
+use x_log4rs_sqlite::IdStrategy;
+use x_log4rs_sqlite::LogFilter;
+use x_log4rs_sqlite::LogReader;
+use x_log4rs_sqlite::SqliteLogAppender;
+use x_log4rs_sqlite::Storage;
+
+fn messages(reader: &LogReader, filter: &LogFilter) -> Vec<String> {
+    reader.entries(filter).map(|e| e.unwrap().message).collect()
+}
+
+#[test]
+fn filters_entries() {
+    for (storage, id_strategy) in [
+        (Storage::Text, IdStrategy::UuidV4),
+        (Storage::Compact, IdStrategy::Rowid),
+    ] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sqlite");
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .storage(storage)
+            .id_strategy(id_strategy)
+            .build()
+            .unwrap();
+        let start = chrono::Utc::now();
+        common::record(&appender, log::Level::Info, "app::db", "connected").unwrap();
+        common::record(&appender, log::Level::Warn, "app::db", "slow query").unwrap();
+        common::record(&appender, log::Level::Error, "app::http", "request failed").unwrap();
+        common::record(&appender, log::Level::Debug, "other", "debug details").unwrap();
+        appender.flush();
+        let reader = LogReader::open(&path).unwrap();
+        let all = reader
+            .entries(&LogFilter::new())
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(all.len(), 4, "{:?}", storage);
+        assert_eq!(all[0].level, log::Level::Info);
+        assert_eq!(all[0].target.as_deref(), Some("app::db"));
+        assert_eq!(all[0].line, Some(common::LINE));
+        assert!(all[0].ts >= start.naive_utc() - chrono::Duration::seconds(1));
+        assert_eq!(
+            messages(&reader, &LogFilter::new().min_level(log::Level::Warn)),
+            vec!["slow query", "request failed"]
+        );
+        assert_eq!(
+            messages(&reader, &LogFilter::new().target_prefix("app::")),
+            vec!["connected", "slow query", "request failed"]
+        );
+        assert_eq!(
+            messages(&reader, &LogFilter::new().message_contains("query")),
+            vec!["slow query"]
+        );
+        assert_eq!(
+            messages(&reader, &LogFilter::new().offset(1).limit(2)),
+            vec!["slow query", "request failed"]
+        );
+        assert_eq!(
+            messages(&reader, &LogFilter::new().newest_first(true).limit(2)),
+            vec!["debug details", "request failed"]
+        );
+        assert!(messages(&reader, &LogFilter::new().since(chrono::Utc::now())).is_empty());
+        assert_eq!(
+            messages(&reader, &LogFilter::new().until(chrono::Utc::now())).len(),
+            4
+        );
+    }
+}
+
+#[test]
+fn reads_more_entries_than_a_page() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(10000)
+        .build()
+        .unwrap();
+    for i in 0..2500 {
+        common::record(&appender, log::Level::Info, "app", &format!("{}", i)).unwrap();
+    }
+    appender.flush();
+    let reader = LogReader::open(&path).unwrap();
+    let messages = messages(&reader, &LogFilter::new());
+    let expected = (0..2500).map(|i| i.to_string()).collect::<Vec<_>>();
+    assert_eq!(messages, expected);
+}
+
+#[test]
+fn reads_mdc() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .mdc_keys(vec!["request_id".to_string()])
+        .mdc_all(true)
+        .build()
+        .unwrap();
+    log_mdc::insert("request_id", "r1");
+    log_mdc::insert("user", "bob");
+    common::record(&appender, log::Level::Info, "app", "with mdc").unwrap();
+    log_mdc::clear();
+    appender.flush();
+    let reader = LogReader::open(&path).unwrap();
+    let entry = reader.entries(&LogFilter::new()).next().unwrap().unwrap();
+    assert_eq!(entry.mdc.get("request_id").map(|s| s.as_str()), Some("r1"));
+    assert_eq!(entry.mdc.get("user").map(|s| s.as_str()), Some("bob"));
+}
+
+#[test]
+fn reads_custom_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .table("app_log")
+        .column(Field::Ts, "logged_at")
+        .column(Field::Message, "msg")
+        .column(Field::Target, "source")
+        .build()
+        .unwrap();
+    let start = chrono::Utc::now();
+    common::record(&appender, log::Level::Info, "app::db", "connected").unwrap();
+    common::record(&appender, log::Level::Warn, "other", "slow query").unwrap();
+    appender.flush();
+    let err = LogReader::open_table(&path, "app_log").err().unwrap();
+    assert_eq!(err.to_string(), "Table `app_log` has no column `ts`");
+    let reader = LogReader::builder()
+        .table("app_log")
+        .column(Field::Ts, "logged_at")
+        .column(Field::Message, "msg")
+        .column(Field::Target, "source")
+        .open(&path)
+        .unwrap();
+    let filter = LogFilter::new()
+        .since(start)
+        .target_prefix("app")
+        .message_contains("conn");
+    let entries = reader
+        .entries(&filter)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].message, "connected");
+    assert_eq!(entries[0].target.as_deref(), Some("app::db"));
+}
+
+#[test]
+fn reads_entries_after_rowid() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder().path(&path).build().unwrap();
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.max_rowid().unwrap(), 0);
+    common::append(&appender, "one");
+    appender.flush();
+    let last = reader.max_rowid().unwrap();
+    common::append(&appender, "two");
+    appender.flush();
+    // Written later, with an older timestamp.
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute(
+            "insert into entry (id, ts, level, message) values ('x', '2020-01-01 00:00:00.000000', 'INFO', 'late')",
+            [],
+        )
+        .unwrap();
+    let filter = LogFilter::new().after_rowid(last);
+    assert_eq!(messages(&reader, &filter), vec!["late", "two"]);
+    let entries = reader
+        .entries(&filter)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries[0].rowid, reader.max_rowid().unwrap());
+}
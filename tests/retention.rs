@@ -0,0 +1,110 @@
+mod common;
+
+use log4rs::append::Append;
+use std::path::Path;
+use std::time::Duration;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+fn messages(path: &Path) -> Vec<String> {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    let mut stmt = conn
+        .prepare("select message from entry order by ts, rowid")
+        .unwrap();
+    stmt.query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<String>>>()
+        .unwrap()
+}
+
+#[test]
+fn keeps_max_rows_newest_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .max_rows(10)
+        .retention_batch_size(3)
+        .build()
+        .unwrap();
+    for i in 0..25 {
+        common::append(&appender, &format!("{:02}", i));
+    }
+    appender.flush();
+    let expected = (15..25).map(|i| format!("{:02}", i)).collect::<Vec<_>>();
+    assert_eq!(messages(&path), expected);
+}
+
+#[test]
+fn limits_batches_per_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(100)
+        .max_rows(10)
+        .retention_batch_size(1)
+        .retention_check_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    for i in 0..25 {
+        common::append(&appender, &format!("{:02}", i));
+    }
+    appender.flush();
+    assert_eq!(messages(&path).len(), 15);
+    std::thread::sleep(Duration::from_millis(100));
+    common::append(&appender, "25");
+    appender.flush();
+    let expected = (16..26).map(|i| format!("{:02}", i)).collect::<Vec<_>>();
+    assert_eq!(messages(&path), expected);
+}
+
+#[test]
+fn deletes_entries_older_than_max_age() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .max_age(Duration::from_secs(24 * 60 * 60))
+        .build()
+        .unwrap();
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute_batch(
+            "insert into entry (id, ts, level, message) values
+                ('a', '2020-01-01 00:00:00.000000', 'INFO', 'old'),
+                ('b', strftime('%Y-%m-%d %H:%M:%f000', 'now', '-1 hour'), 'INFO', 'recent');",
+        )
+        .unwrap();
+    common::append(&appender, "new");
+    appender.flush();
+    assert_eq!(messages(&path), vec!["recent", "new"]);
+}
+
+#[test]
+fn keeps_db_under_max_db_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(10000)
+        .max_db_size(256 * 1024)
+        .retention_batch_size(100)
+        .retention_check_interval(Duration::from_millis(1))
+        .incremental_vacuum(true)
+        .build()
+        .unwrap();
+    // Each check deletes a limited number of batches, so the entries are
+    // written in rounds, with a check after each.
+    for i in 0..5000 {
+        common::append(&appender, &format!("{:04} {}", i, "x".repeat(200)));
+        if i % 250 == 249 {
+            std::thread::sleep(Duration::from_millis(1));
+            appender.flush();
+        }
+    }
+    let messages = messages(&path);
+    assert!(messages.len() < 5000);
+    assert!(messages.last().unwrap().starts_with("4999 "));
+    let size = std::fs::metadata(&path).unwrap().len();
+    assert!(size <= 256 * 1024, "unexpected size: {}", size);
+}
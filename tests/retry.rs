@@ -0,0 +1,141 @@
+mod common;
+
+use log4rs::append::Append;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use x_log4rs_sqlite::SqliteLogAppender;
+use x_log4rs_sqlite::Stats;
+
+#[test]
+fn retries_batch_until_lock_is_released() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .background(true)
+        .buffer_size(1)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(10)
+        .retry_backoff(Duration::from_millis(20))
+        .build()
+        .unwrap();
+    let stats = appender.stats_handle();
+    let conn = common::lock(&path);
+    common::append(&appender, "one");
+    std::thread::sleep(Duration::from_millis(100));
+    conn.execute_batch("commit").unwrap();
+    // Retried by the writer thread, without further entries or flushes.
+    let start = Instant::now();
+    while stats.get().written == 0 && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let stats = stats.get();
+    assert_eq!(stats.written, 1);
+    assert!(stats.retries > 0);
+    assert_eq!(stats.failed_batches, 0);
+}
+
+#[test]
+fn does_not_wait_for_retries_when_logging() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(1)
+        .busy_timeout(Duration::from_millis(200))
+        .max_retries(3)
+        .retry_backoff(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    let conn = common::lock(&path);
+    let start = Instant::now();
+    for i in 0..5 {
+        common::append(&appender, &i.to_string());
+    }
+    // Only the first entry waits for the busy timeout.
+    assert!(
+        start.elapsed() < Duration::from_millis(400),
+        "appends took {:?}",
+        start.elapsed()
+    );
+    assert_eq!(appender.stats(), Stats::default());
+    conn.execute_batch("commit").unwrap();
+    appender.try_flush().unwrap();
+    let stats = appender.stats();
+    assert_eq!(stats.written, 5);
+    assert_eq!(stats.retries, 1);
+}
+
+#[test]
+fn counts_failed_batch_after_last_retry() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(1)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(2)
+        .retry_backoff(Duration::from_millis(1))
+        .build()
+        .unwrap();
+    let stats = appender.stats_handle();
+    let conn = common::lock(&path);
+    // The first attempt and the retry fail quietly, the last retry fails with
+    // an error.
+    common::append(&appender, "one");
+    std::thread::sleep(Duration::from_millis(10));
+    common::append(&appender, "two");
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(common::try_append(&appender, log::Level::Info, "three").is_err());
+    conn.execute_batch("commit").unwrap();
+    appender.flush();
+    assert_eq!(
+        stats.get(),
+        Stats {
+            written: 3,
+            retries: 2,
+            failed_batches: 1,
+            dropped: 0,
+            forwarded: 0,
+        }
+    );
+}
+
+#[test]
+fn waits_longest_backoff_after_persistent_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let errors = Arc::new(AtomicUsize::new(0));
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .background(true)
+        .buffer_size(1)
+        .max_retries(3)
+        .retry_backoff(Duration::from_millis(50))
+        .on_error({
+            let errors = errors.clone();
+            move |_| {
+                errors.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .build()
+        .unwrap();
+    let stats = appender.stats_handle();
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute_batch(
+            "create trigger reject before insert on entry
+             begin select raise(abort, 'rejected'); end",
+        )
+        .unwrap();
+    common::append(&appender, "one");
+    // Not retried, and attempted again only after 400ms.
+    std::thread::sleep(Duration::from_millis(1000));
+    let errors = errors.load(Ordering::Relaxed);
+    assert!((1..=3).contains(&errors), "{} errors", errors);
+    let stats = stats.get();
+    assert_eq!(stats.retries, 0);
+    assert_eq!(stats.failed_batches, 1);
+}
@@ -0,0 +1,47 @@
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::Append;
+use std::path::Path;
+use x_log4rs_sqlite::RollTrigger;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+fn count(path: &Path) -> i64 {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.query_row("select count(*) from entry", [], |row| row.get(0))
+        .unwrap()
+}
+
+#[test]
+fn rolls_over_by_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let pattern = dir.path().join("log.{}.sqlite");
+    let roller = FixedWindowRoller::builder()
+        .build(pattern.to_str().unwrap(), 2)
+        .unwrap();
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(100)
+        .rolling(RollTrigger::Size(32 * 1024), Box::new(roller))
+        .build()
+        .unwrap();
+    for i in 0..1000 {
+        appender
+            .append(
+                &log::Record::builder()
+                    .level(log::Level::Info)
+                    .args(format_args!("{} {}", i, "x".repeat(100)))
+                    .build(),
+            )
+            .unwrap();
+    }
+    appender.flush();
+    let archives = [
+        dir.path().join("log.0.sqlite"),
+        dir.path().join("log.1.sqlite"),
+    ];
+    assert!(archives.iter().all(|p| p.exists()));
+    assert!(!dir.path().join("log.2.sqlite").exists());
+    let total = count(&path) + archives.iter().map(|p| count(p)).sum::<i64>();
+    assert!(total < 1000);
+    assert!(count(&path) > 0);
+}
@@ -0,0 +1,344 @@
+mod common;
+
+use log4rs::append::Append;
+use std::path::Path;
+use std::path::PathBuf;
+use x_log4rs_sqlite::Field;
+use x_log4rs_sqlite::IdStrategy;
+use x_log4rs_sqlite::SqliteLogAppender;
+use x_log4rs_sqlite::Storage;
+
+// DBs with three entries written by the released versions of the crate.
+enum Fixture {
+    // DB file saved by the crate.
+    Db(&'static [u8]),
+    // Dump of a DB saved by the crate.
+    Sql(&'static str),
+}
+
+const FIXTURES: &[(&str, Fixture)] = &[
+    (
+        "0.0.1",
+        Fixture::Db(include_bytes!("fixtures/0.0.1.sqlite")),
+    ),
+    (
+        "0.0.2",
+        Fixture::Db(include_bytes!("fixtures/0.0.2.sqlite")),
+    ),
+    ("0.0.3", Fixture::Sql(include_str!("fixtures/0.0.3.sql"))),
+];
+
+fn create_fixture_db(dir: &Path, fixture: &Fixture) -> PathBuf {
+    match fixture {
+        Fixture::Db(db) => {
+            let path = dir.join("log.sqlite");
+            std::fs::write(&path, db).unwrap();
+            path
+        }
+        Fixture::Sql(sql) => create_db(dir, sql),
+    }
+}
+
+fn create_db(dir: &Path, sql: &str) -> PathBuf {
+    let path = dir.join("log.sqlite");
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute_batch(sql).unwrap();
+    path
+}
+
+fn query_string(path: &Path, sql: &str) -> String {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.query_row(sql, [], |row| row.get(0)).unwrap()
+}
+
+fn query_i64(path: &Path, sql: &str) -> i64 {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.query_row(sql, [], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn upgrades_released_versions() {
+    for (version, fixture) in FIXTURES {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_fixture_db(dir.path(), fixture);
+        let appender = SqliteLogAppender::builder().path(&path).build().unwrap();
+        common::append(&appender, "after upgrade");
+        appender.flush();
+        assert_eq!(
+            query_i64(&path, "select count(*) from entry"),
+            4,
+            "fixture {}",
+            version
+        );
+        assert_eq!(
+            query_string(
+                &path,
+                "select level from entry where message = 'test log message 999997'"
+            ),
+            "WARN",
+            "fixture {}",
+            version
+        );
+        assert_eq!(
+            query_i64(&path, "select count(distinct id) from entry"),
+            4,
+            "fixture {}",
+            version
+        );
+        assert_eq!(
+            query_string(
+                &path,
+                "select target from entry where message = 'after upgrade'"
+            ),
+            common::TARGET,
+            "fixture {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn upgraded_schema_matches_new_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    let new_path = dir.path().join("new.sqlite");
+    SqliteLogAppender::builder()
+        .path(&new_path)
+        .build()
+        .unwrap();
+    let columns = |path: &Path| {
+        let conn = rusqlite::Connection::open(path).unwrap();
+        let mut stmt = conn.prepare("pragma table_info(entry)").unwrap();
+        stmt.query_map([], |row| row.get::<_, String>(1))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap()
+    };
+    for (version, fixture) in FIXTURES {
+        let dir = tempfile::tempdir().unwrap();
+        let path = create_fixture_db(dir.path(), fixture);
+        SqliteLogAppender::builder().path(&path).build().unwrap();
+        assert_eq!(columns(&path), columns(&new_path), "fixture {}", version);
+        assert_eq!(
+            query_i64(
+                &path,
+                "select version from x_log4rs_sqlite_schema where table_name = 'entry'"
+            ),
+            query_i64(
+                &new_path,
+                "select version from x_log4rs_sqlite_schema where table_name = 'entry'"
+            ),
+            "fixture {}",
+            version
+        );
+    }
+}
+
+#[test]
+fn reopens_current_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    for message in ["first", "second"] {
+        let appender = SqliteLogAppender::builder().path(&path).build().unwrap();
+        common::append(&appender, message);
+        appender.flush();
+    }
+    assert_eq!(query_i64(&path, "select count(*) from entry"), 2);
+}
+
+#[test]
+fn refuses_newer_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    SqliteLogAppender::builder().path(&path).build().unwrap();
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute(
+            "update x_log4rs_sqlite_schema set version = version + 1",
+            [],
+        )
+        .unwrap();
+    let err = SqliteLogAppender::builder()
+        .path(&path)
+        .build()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("schema version"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn writes_custom_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .table("app_log")
+        .column(Field::Ts, "logged_at")
+        .column(Field::Message, "msg")
+        .build()
+        .unwrap();
+    common::append(&appender, "custom");
+    appender.flush();
+    assert_eq!(
+        query_string(&path, "select msg from app_log where logged_at is not null"),
+        "custom"
+    );
+}
+
+#[test]
+fn adds_columns_mapped_after_table_was_created() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    SqliteLogAppender::builder().path(&path).build().unwrap();
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .column(Field::Target, "source")
+        .build()
+        .unwrap();
+    common::append(&appender, "mapped");
+    appender.flush();
+    assert_eq!(
+        query_string(&path, "select source from entry where message = 'mapped'"),
+        common::TARGET
+    );
+    let err = SqliteLogAppender::builder()
+        .path(&path)
+        .create_schema(false)
+        .column(Field::Line, "line_number")
+        .build()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("has no column `line_number`"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn writes_existing_table_without_creating_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = create_db(
+        dir.path(),
+        "create table events (uid text primary key, at text, severity text, body text, target text)",
+    );
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .table("events")
+        .create_schema(false)
+        .column(Field::Id, "uid")
+        .column(Field::Ts, "at")
+        .column(Field::Level, "severity")
+        .column(Field::Message, "body")
+        .build()
+        .unwrap();
+    common::append(&appender, "existing");
+    appender.flush();
+    assert_eq!(
+        query_string(&path, "select target from events where body = 'existing'"),
+        common::TARGET
+    );
+    assert_eq!(
+        query_i64(
+            &path,
+            "select count(*) from sqlite_master where name = 'x_log4rs_sqlite_schema'"
+        ),
+        0
+    );
+}
+
+#[test]
+fn refuses_table_missing_required_column() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = create_db(
+        dir.path(),
+        "create table events (id text, ts text, level text)",
+    );
+    let err = SqliteLogAppender::builder()
+        .path(&path)
+        .table("events")
+        .create_schema(false)
+        .build()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("has no column `message`"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn stores_compact_timestamps_and_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .storage(Storage::Compact)
+        .build()
+        .unwrap();
+    common::append(&appender, "compact");
+    appender.flush();
+    assert_eq!(query_i64(&path, "select level from entry"), 3);
+    let ts = query_i64(&path, "select ts from entry");
+    assert!(ts > 1_600_000_000_000_000, "unexpected ts: {}", ts);
+    assert_eq!(query_string(&path, "select level from entry_view"), "INFO");
+    let expected = chrono::DateTime::from_timestamp_micros(ts)
+        .unwrap()
+        .format("%Y-%m-%d %H:%M:%S%.6f")
+        .to_string();
+    assert_eq!(query_string(&path, "select ts from entry_view"), expected);
+    let err = SqliteLogAppender::builder()
+        .path(&path)
+        .build()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("`compact` storage"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn generates_ids_in_log_order() {
+    for id_strategy in [IdStrategy::UuidV7, IdStrategy::Ulid, IdStrategy::Rowid] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sqlite");
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .id_strategy(id_strategy)
+            .build()
+            .unwrap();
+        for i in 0..100 {
+            common::append(&appender, &format!("{:03}", i));
+        }
+        appender.flush();
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let messages = conn
+            .prepare("select message from entry order by id")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        let expected = (0..100).map(|i| format!("{:03}", i)).collect::<Vec<_>>();
+        assert_eq!(messages, expected, "{:?}", id_strategy);
+    }
+}
+
+#[test]
+fn refuses_id_strategy_not_matching_table() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    SqliteLogAppender::builder().path(&path).build().unwrap();
+    let err = SqliteLogAppender::builder()
+        .path(&path)
+        .id_strategy(IdStrategy::Rowid)
+        .build()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("text ids"),
+        "unexpected error: {}",
+        err
+    );
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use log4rs::append::Append;
+use x_log4rs_sqlite::LogReader;
+use x_log4rs_sqlite::SqliteLogAppender;
+use x_log4rs_sqlite::Storage;
+
+#[test]
+fn finds_matching_messages() {
+    for storage in [Storage::Text, Storage::Compact] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sqlite");
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .storage(storage)
+            .full_text_search(true)
+            .build()
+            .unwrap();
+        common::append_level(&appender, log::Level::Info, "connected to db");
+        common::append_level(&appender, log::Level::Warn, "connection refused by db");
+        common::append_level(&appender, log::Level::Error, "disk full");
+        appender.flush();
+        let mut reader = LogReader::open(&path).unwrap();
+        reader.set_snippet_markers("<", ">");
+        let hits = reader.search("refused", 10).unwrap();
+        assert_eq!(hits.len(), 1, "{:?}", storage);
+        assert_eq!(hits[0].entry.message, "connection refused by db");
+        assert_eq!(hits[0].snippet, "connection <refused> by db");
+        assert_eq!(hits[0].entry.level, log::Level::Warn);
+        assert_eq!(hits[0].entry.target.as_deref(), Some(common::TARGET));
+        assert_eq!(reader.search("db", 10).unwrap().len(), 2);
+        assert_eq!(reader.search("db", 1).unwrap().len(), 1);
+    }
+}
+
+#[test]
+fn indexes_existing_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder().path(&path).build().unwrap();
+    common::append_level(&appender, log::Level::Info, "written before the index");
+    appender.flush();
+    drop(appender);
+    SqliteLogAppender::builder()
+        .path(&path)
+        .full_text_search(true)
+        .build()
+        .unwrap();
+    let reader = LogReader::open(&path).unwrap();
+    assert_eq!(reader.search("index", 10).unwrap().len(), 1);
+}
+
+#[test]
+fn refuses_search_without_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    SqliteLogAppender::builder().path(&path).build().unwrap();
+    let err = LogReader::open(&path).unwrap().search("x", 10).unwrap_err();
+    assert!(
+        err.to_string().contains("full_text_search"),
+        "unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn refuses_index_without_create_schema() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let err = SqliteLogAppender::builder()
+        .path(&path)
+        .create_schema(false)
+        .full_text_search(true)
+        .build()
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Invalid `full_text_search`"),
+        "unexpected error: {}",
+        err
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use log4rs::append::Append;
+use log4rs::config::{Appender, Config, Root};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use x_log4rs_sqlite::SqliteLogAppender;
+
+// The logger is global, so the tests using it take turns replacing its config.
+static LOGGER: Mutex<Option<log4rs::Handle>> = Mutex::new(None);
+
+fn set_logger(appender: SqliteLogAppender) -> MutexGuard<'static, Option<log4rs::Handle>> {
+    let mut handle = LOGGER.lock().unwrap_or_else(PoisonError::into_inner);
+    let config = Config::builder()
+        .appender(Appender::builder().build("sqlite", Box::new(appender)))
+        .build(
+            Root::builder()
+                .appender("sqlite")
+                .build(log::LevelFilter::Info),
+        )
+        .unwrap();
+    match handle.as_ref() {
+        Some(handle) => handle.set_config(config),
+        None => {
+            *handle = Some(log4rs::init_config(config).unwrap());
+            x_log4rs_sqlite::install_panic_hook();
+        }
+    }
+    handle
+}
+
+fn entries(path: &Path) -> Vec<(String, String)> {
+    rusqlite::Connection::open(path)
+        .unwrap()
+        .prepare("select target, message from entry order by ts, rowid")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap()
+}
+
+#[derive(Debug)]
+struct PanickingAppender;
+
+impl Append for PanickingAppender {
+    fn append(&self, _record: &log::Record) -> anyhow::Result<()> {
+        panic!("fallback appender failed");
+    }
+    fn flush(&self) {}
+}
+
+#[test]
+fn flushes_buffer_on_drop() {
+    for background in [false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sqlite");
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .background(background)
+            .buffer_size(100)
+            .build()
+            .unwrap();
+        common::append(&appender, "one");
+        common::append(&appender, "two");
+        assert!(entries(&path).is_empty(), "background: {}", background);
+        drop(appender);
+        assert_eq!(
+            entries(&path),
+            vec![
+                (common::TARGET.to_string(), "one".to_string()),
+                (common::TARGET.to_string(), "two".to_string()),
+            ],
+            "background: {}",
+            background
+        );
+    }
+}
+
+#[test]
+fn logs_panics() {
+    for background in [false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.sqlite");
+        let appender = SqliteLogAppender::builder()
+            .path(&path)
+            .background(background)
+            .buffer_size(100)
+            .build()
+            .unwrap();
+        let _logger = set_logger(appender);
+        log::info!(target: "app", "before the panic");
+        let result = std::thread::Builder::new()
+            .name("worker".to_string())
+            .spawn(|| panic!("boom"))
+            .unwrap()
+            .join();
+        assert!(result.is_err());
+        // Written by the hook, while the appender is still in use.
+        let entries = entries(&path);
+        assert_eq!(entries.len(), 2, "background: {}", background);
+        assert_eq!(entries[0].1, "before the panic");
+        assert_eq!(entries[1].0, "panic");
+        assert!(
+            entries[1].1.starts_with("thread 'worker' panicked at")
+                && entries[1].1.contains("boom"),
+            "unexpected message: {}",
+            entries[1].1
+        );
+    }
+}
+
+#[test]
+fn does_not_deadlock_when_appender_panics() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("log.sqlite");
+    let appender = SqliteLogAppender::builder()
+        .path(&path)
+        .buffer_size(1)
+        .busy_timeout(Duration::from_millis(1))
+        .max_retries(0)
+        .fallback(Box::new(PanickingAppender))
+        .on_error(|_| {})
+        .build()
+        .unwrap();
+    let _logger = set_logger(appender);
+    let conn = common::lock(&path);
+    // The fallback appender panics while the appender is locked, and the
+    // panic hook logs through the same appender.
+    let (done_tx, done_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let result = std::thread::spawn(|| log::info!(target: "app", "one")).join();
+        done_tx.send(result.is_err()).unwrap();
+    });
+    let panicked = done_rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("panic hook deadlocked");
+    assert!(panicked);
+    drop(conn);
+    log::info!(target: "app", "two");
+    log::logger().flush();
+    assert_eq!(entries(&path), vec![("app".to_string(), "two".to_string())]);
+}